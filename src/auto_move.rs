@@ -0,0 +1,416 @@
+use super::{
+    CombatStats, GameLog, Item, Map, Monster, Name, Player, Position, RunState, TileType, Viewshed,
+};
+use crate::player::try_move_player;
+use rltk::{DijkstraMap, Point, VirtualKeyCode};
+use specs::prelude::*;
+
+const EXPLORE_MAX_DEPTH: f32 = 1024.0;
+const REST_TURNS_PER_HP: i32 = 3;
+const CARDINALS: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+/// Multi-turn action requested by the player. While it is not `Idle`, one
+/// turn of it is played instead of waiting for a key press.
+#[derive(PartialEq, Clone)]
+pub enum AutoMove {
+    Idle,
+    Travel {
+        destination: usize,
+    },
+    Explore,
+    Rest {
+        last_hp: i32,
+        turns: i32,
+    },
+    Run {
+        delta_x: i32,
+        delta_y: i32,
+        openings: usize,
+        known_monsters: Vec<Entity>,
+    },
+}
+
+/// Key whose press started the current automatic movement. Key repeat keeps
+/// reporting it while it is held, so it does not count as an interruption.
+pub struct StartKey(pub Option<VirtualKeyCode>);
+
+/// Whether `key` should interrupt automatic movement: any key other than the
+/// one that started it.
+pub fn is_interrupting_key(ecs: &World, key: Option<VirtualKeyCode>) -> bool {
+    key.is_some() && key != ecs.fetch::<StartKey>().0
+}
+
+/// Starts travelling to the given tile, if it is a known floor tile.
+pub fn start_travel(ecs: &mut World, x: i32, y: i32) {
+    let destination;
+    {
+        let map = ecs.fetch::<Map>();
+        if x < 0 || x >= map.width || y < 0 || y >= map.height {
+            return;
+        }
+        destination = map.get_index_xy(x, y);
+        if !map.revealed_tiles[destination] || !map.tiles[destination].is_walkable() {
+            return;
+        }
+    }
+    start(ecs, AutoMove::Travel { destination });
+}
+
+/// Starts walking towards the nearest unexplored part of the map.
+pub fn start_explore(ecs: &mut World) {
+    start(ecs, AutoMove::Explore);
+}
+
+/// Starts waiting turn after turn until the player is fully healed.
+pub fn start_rest(ecs: &mut World) {
+    let hp;
+    {
+        let player_entity = ecs.fetch::<Entity>();
+        let combat_stats = ecs.read_storage::<CombatStats>();
+        let stats = combat_stats.get(*player_entity).unwrap();
+        if stats.hp >= stats.max_hp {
+            ecs.fetch_mut::<GameLog>()
+                .entries
+                .push("You are already at full health.".to_string());
+            return;
+        }
+        hp = stats.hp;
+    }
+    start(
+        ecs,
+        AutoMove::Rest {
+            last_hp: hp,
+            turns: 0,
+        },
+    );
+}
+
+/// Starts running in a direction until something interesting happens.
+/// Monsters already in view when the run starts do not stop it.
+pub fn start_run(ecs: &mut World, delta_x: i32, delta_y: i32) {
+    let openings = {
+        let map = ecs.fetch::<Map>();
+        let player_pos = ecs.fetch::<Point>();
+        count_openings(&map, player_pos.x, player_pos.y)
+    };
+    let known_monsters = visible_monsters(ecs);
+    *ecs.write_resource::<AutoMove>() = AutoMove::Run {
+        delta_x,
+        delta_y,
+        openings,
+        known_monsters,
+    };
+}
+
+fn start(ecs: &mut World, action: AutoMove) {
+    if hostile_in_view(ecs) {
+        ecs.fetch_mut::<GameLog>()
+            .entries
+            .push("Not with enemies in sight!".to_string());
+        return;
+    }
+    *ecs.write_resource::<AutoMove>() = action;
+}
+
+fn halt(ecs: &mut World) {
+    *ecs.write_resource::<AutoMove>() = AutoMove::Idle;
+}
+
+fn stop(ecs: &mut World, reason: &str) {
+    *ecs.write_resource::<AutoMove>() = AutoMove::Idle;
+    ecs.fetch_mut::<GameLog>().entries.push(reason.to_string());
+}
+
+/// Takes the next step of the current automatic movement. Returns `None` when
+/// there is nothing in progress, so the caller should read input as usual.
+pub fn auto_move_step(ecs: &mut World, interrupted: bool) -> Option<RunState> {
+    let action = (*ecs.fetch::<AutoMove>()).clone();
+    if action == AutoMove::Idle {
+        return None;
+    }
+
+    if interrupted {
+        stop(ecs, "You stop.");
+        return Some(RunState::AwaitingInput);
+    }
+
+    if let AutoMove::Run {
+        delta_x,
+        delta_y,
+        openings,
+        known_monsters,
+    } = action
+    {
+        return Some(run_step(ecs, delta_x, delta_y, openings, known_monsters));
+    }
+
+    if hostile_in_view(ecs) {
+        stop(ecs, "You spot an enemy and stop.");
+        return Some(RunState::AwaitingInput);
+    }
+
+    if let AutoMove::Rest { last_hp, turns } = action {
+        return match rest_turn(ecs, last_hp, turns) {
+            Err(reason) => {
+                stop(ecs, reason);
+                Some(RunState::AwaitingInput)
+            }
+            Ok(()) => Some(RunState::PlayerTurn),
+        };
+    }
+
+    let next_step = match action {
+        AutoMove::Idle | AutoMove::Rest { .. } | AutoMove::Run { .. } => unreachable!(),
+        AutoMove::Travel { destination } => next_travel_step(ecs, destination),
+        AutoMove::Explore => next_explore_step(ecs),
+    };
+
+    match next_step {
+        Err(reason) => {
+            stop(ecs, reason);
+            Some(RunState::AwaitingInput)
+        }
+        Ok(index) if ecs.fetch::<Map>().tiles[index].is_hazard() => {
+            stop(ecs, "You stop short of the lava.");
+            Some(RunState::AwaitingInput)
+        }
+        Ok(index) => {
+            let player_pos = *ecs.fetch::<Point>();
+            let width = ecs.fetch::<Map>().width;
+            let delta_x = index as i32 % width - player_pos.x;
+            let delta_y = index as i32 / width - player_pos.y;
+            try_move_player(delta_x, delta_y, ecs);
+
+            if let Some(item_name) = item_underfoot(ecs) {
+                stop(ecs, &format!("You see a {} here.", item_name));
+            }
+            Some(RunState::PlayerTurn)
+        }
+    }
+}
+
+fn rest_turn(ecs: &mut World, last_hp: i32, turns: i32) -> Result<(), &'static str> {
+    let player_entity = *ecs.fetch::<Entity>();
+    let mut combat_stats = ecs.write_storage::<CombatStats>();
+    let stats = combat_stats.get_mut(player_entity).unwrap();
+
+    if stats.hp < last_hp {
+        return Err("You are hurt and stop resting.");
+    }
+    if stats.hp >= stats.max_hp {
+        return Err("You feel rested.");
+    }
+
+    // Regenerate slowly: one hit point every few turns of rest
+    let turns = turns + 1;
+    if turns % REST_TURNS_PER_HP == 0 {
+        stats.hp += 1;
+    }
+    *ecs.write_resource::<AutoMove>() = AutoMove::Rest {
+        last_hp: stats.hp,
+        turns,
+    };
+    Ok(())
+}
+
+fn run_step(
+    ecs: &mut World,
+    delta_x: i32,
+    delta_y: i32,
+    openings: usize,
+    known_monsters: Vec<Entity>,
+) -> RunState {
+    if visible_monsters(ecs)
+        .iter()
+        .any(|monster| !known_monsters.contains(monster))
+    {
+        stop(ecs, "You spot an enemy and stop running.");
+        return RunState::AwaitingInput;
+    }
+
+    let direction = {
+        let map = ecs.fetch::<Map>();
+        let player_pos = ecs.fetch::<Point>();
+        let (delta_x, delta_y) = follow_corridor(&map, *player_pos, delta_x, delta_y);
+
+        let next_x = player_pos.x + delta_x;
+        let next_y = player_pos.y + delta_y;
+        if next_x < 1 || next_x > map.width - 1 || next_y < 1 || next_y > map.height - 1 {
+            None
+        } else {
+            let next = map.get_index_xy(next_x, next_y);
+            let enterable = matches!(map.tiles[next], TileType::Floor | TileType::DownStairs)
+                || is_open_door(&map, next);
+            if enterable && !map.blocked[next] {
+                Some((delta_x, delta_y))
+            } else {
+                None
+            }
+        }
+    };
+    let (delta_x, delta_y) = match direction {
+        Some(direction) => direction,
+        None => {
+            halt(ecs);
+            return RunState::AwaitingInput;
+        }
+    };
+    try_move_player(delta_x, delta_y, ecs);
+
+    if let Some(item_name) = item_underfoot(ecs) {
+        stop(ecs, &format!("You see a {} here.", item_name));
+        return RunState::PlayerTurn;
+    }
+
+    // Doorways, stairs, corridor branches and room edges change the shape
+    // of the surroundings, which is where a run should end
+    let (new_openings, on_landmark) = {
+        let map = ecs.fetch::<Map>();
+        let player_pos = ecs.fetch::<Point>();
+        let index = map.get_index_xy(player_pos.x, player_pos.y);
+        (
+            count_openings(&map, player_pos.x, player_pos.y),
+            is_open_door(&map, index) || map.tiles[index] == TileType::DownStairs,
+        )
+    };
+    if on_landmark || new_openings != openings {
+        halt(ecs);
+    } else {
+        *ecs.write_resource::<AutoMove>() = AutoMove::Run {
+            delta_x,
+            delta_y,
+            openings: new_openings,
+            known_monsters,
+        };
+    }
+    RunState::PlayerTurn
+}
+
+fn is_open_door(map: &Map, index: usize) -> bool {
+    map.tiles[index] == TileType::DoorOpen
+}
+
+/// Counts the walkable tiles orthogonally next to `(x, y)`. Diagonals are
+/// left out so that a corridor corner still has exactly two ways out.
+fn count_openings(map: &Map, x: i32, y: i32) -> usize {
+    CARDINALS
+        .iter()
+        .filter(|(dx, dy)| {
+            let (nx, ny) = (x + dx, y + dy);
+            nx >= 0
+                && nx < map.width
+                && ny >= 0
+                && ny < map.height
+                && map.tiles[map.get_index_xy(nx, ny)].is_walkable()
+        })
+        .count()
+}
+
+/// Turns with the corridor when running straight along one: a corridor tile
+/// has exactly two ways out, so keep taking the one we did not come from.
+fn follow_corridor(map: &Map, pos: Point, delta_x: i32, delta_y: i32) -> (i32, i32) {
+    if delta_x != 0 && delta_y != 0 || count_openings(map, pos.x, pos.y) != 2 {
+        return (delta_x, delta_y);
+    }
+
+    CARDINALS
+        .iter()
+        .copied()
+        .filter(|(dx, dy)| map.tiles[map.get_index_xy(pos.x + dx, pos.y + dy)].is_walkable())
+        .find(|direction| *direction != (-delta_x, -delta_y))
+        .unwrap_or((delta_x, delta_y))
+}
+
+fn next_travel_step(ecs: &World, destination: usize) -> Result<usize, &'static str> {
+    let map = ecs.fetch::<Map>();
+    let player_pos = ecs.fetch::<Point>();
+    let start = map.get_index_xy(player_pos.x, player_pos.y);
+
+    if start == destination {
+        return Err("You arrive at your destination.");
+    }
+
+    let path = rltk::a_star_search(start, destination, &*map);
+    if path.success && path.steps.len() > 1 {
+        Ok(path.steps[1])
+    } else {
+        Err("There is no way there.")
+    }
+}
+
+fn next_explore_step(ecs: &World) -> Result<usize, &'static str> {
+    let map = ecs.fetch::<Map>();
+    let player_pos = ecs.fetch::<Point>();
+    let start = map.get_index_xy(player_pos.x, player_pos.y);
+
+    // Known walkable tiles touching the unknown are the places worth walking to
+    let mut frontier: Vec<usize> = Vec::new();
+    for y in 1..map.height - 1 {
+        for x in 1..map.width - 1 {
+            let index = map.get_index_xy(x, y);
+            if !map.revealed_tiles[index] || !map.tiles[index].is_walkable() {
+                continue;
+            }
+            let touches_unknown = (-1..=1)
+                .any(|dy| (-1..=1).any(|dx| !map.revealed_tiles[map.get_index_xy(x + dx, y + dy)]));
+            if touches_unknown {
+                frontier.push(index);
+            }
+        }
+    }
+
+    if frontier.is_empty() {
+        return Err("There is nothing left to explore.");
+    }
+
+    let mut dijkstra_map =
+        DijkstraMap::new(map.width, map.height, &frontier, &*map, EXPLORE_MAX_DEPTH);
+    // The search leaves its start tiles at whatever a neighbour gave them,
+    // which would hide the frontier from a player standing next to it
+    for index in frontier.iter() {
+        dijkstra_map.map[*index] = 0.0;
+    }
+    match DijkstraMap::find_lowest_exit(&dijkstra_map, start, &*map) {
+        Some(exit) if dijkstra_map.map[exit] < dijkstra_map.map[start] => Ok(exit),
+        _ => Err("There is nothing left to explore."),
+    }
+}
+
+fn hostile_in_view(ecs: &World) -> bool {
+    !visible_monsters(ecs).is_empty()
+}
+
+fn visible_monsters(ecs: &World) -> Vec<Entity> {
+    let entities = ecs.entities();
+    let players = ecs.read_storage::<Player>();
+    let viewsheds = ecs.read_storage::<Viewshed>();
+    let monsters = ecs.read_storage::<Monster>();
+    let positions = ecs.read_storage::<Position>();
+    let map = ecs.fetch::<Map>();
+
+    let mut visible: Vec<Entity> = Vec::new();
+    for (_player, viewshed, player_pos) in (&players, &viewsheds, &positions).join() {
+        let viewer = Point::new(player_pos.x, player_pos.y);
+        for (entity, _monster, pos) in (&entities, &monsters, &positions).join() {
+            let index = map.get_index_xy(pos.x, pos.y);
+            if viewshed.visible_tiles.contains(&Point::new(pos.x, pos.y))
+                && map.can_spot(index, viewer)
+            {
+                visible.push(entity);
+            }
+        }
+    }
+    visible
+}
+
+fn item_underfoot(ecs: &World) -> Option<String> {
+    let player_pos = ecs.fetch::<Point>();
+    let items = ecs.read_storage::<Item>();
+    let names = ecs.read_storage::<Name>();
+    let positions = ecs.read_storage::<Position>();
+
+    (&items, &names, &positions)
+        .join()
+        .find(|(_item, _name, pos)| pos.x == player_pos.x && pos.y == player_pos.y)
+        .map(|(_item, name, _pos)| name.name.clone())
+}
@@ -0,0 +1,165 @@
+use super::{Map, Rect};
+use rltk::RandomNumberGenerator;
+
+/// Leaves are never split into anything narrower or shorter than this.
+const MIN_LEAF: i32 = 10;
+/// Interior leaves are whole rooms, so they can be smaller.
+const MIN_INTERIOR_LEAF: i32 = 9;
+/// Smallest room put inside a leaf, in floor tiles across.
+const MIN_ROOM: i32 = 4;
+/// An area this much longer one way than the other is split across it.
+const SPLIT_RATIO: f32 = 1.25;
+
+/// Builds a level by splitting the map in two, again and again, and putting
+/// one room in each leaf. Sibling areas are joined by a corridor, so the
+/// rooms are spread evenly and every one of them is connected.
+pub fn build(depth: i32, rng: &mut RandomNumberGenerator) -> Map {
+    build_with(depth, rng, false)
+}
+
+/// Like `build`, but each leaf is a room filling it wall to wall, so the
+/// level reads as the inside of a building.
+pub fn build_interior(depth: i32, rng: &mut RandomNumberGenerator) -> Map {
+    build_with(depth, rng, true)
+}
+
+fn build_with(depth: i32, rng: &mut RandomNumberGenerator, interior: bool) -> Map {
+    let mut map = Map::new(depth);
+    let area = Rect::new(0, 0, map.width - 1, map.height - 1);
+    map.rooms = build_node(&mut map, area, rng, interior);
+    map.finish_rooms(rng);
+    map
+}
+
+/// Carves the rooms under `area` and returns them, after joining the rooms
+/// of its two halves.
+fn build_node(
+    map: &mut Map,
+    area: Rect,
+    rng: &mut RandomNumberGenerator,
+    interior: bool,
+) -> Vec<Rect> {
+    let min_leaf = if interior {
+        MIN_INTERIOR_LEAF
+    } else {
+        MIN_LEAF
+    };
+    match split(&area, min_leaf, rng) {
+        None => {
+            let room = if interior {
+                // One wall shared with each neighbour
+                Rect::new(
+                    area.x1,
+                    area.y1,
+                    area.x2 - area.x1 - 1,
+                    area.y2 - area.y1 - 1,
+                )
+            } else {
+                leaf_room(&area, rng)
+            };
+            map.apply_room_to_map(&room);
+            map.take_snapshot();
+            vec![room]
+        }
+        Some((first, second)) => {
+            let mut rooms = build_node(map, first, rng, interior);
+            let second_rooms = build_node(map, second, rng, interior);
+            join_closest(map, &rooms, &second_rooms, rng);
+            map.take_snapshot();
+            rooms.extend(second_rooms);
+            rooms
+        }
+    }
+}
+
+/// Splits `area` across its longer side, or returns `None` for a leaf.
+fn split(area: &Rect, min_leaf: i32, rng: &mut RandomNumberGenerator) -> Option<(Rect, Rect)> {
+    let width = area.x2 - area.x1;
+    let height = area.y2 - area.y1;
+    let vertical = match (width >= min_leaf * 2, height >= min_leaf * 2) {
+        (false, false) => return None,
+        (true, false) => true,
+        (false, true) => false,
+        (true, true) => {
+            if width as f32 > height as f32 * SPLIT_RATIO {
+                true
+            } else if height as f32 > width as f32 * SPLIT_RATIO {
+                false
+            } else {
+                rng.range(0, 2) == 0
+            }
+        }
+    };
+
+    if vertical {
+        let x = rng.range(area.x1 + min_leaf, area.x2 - min_leaf + 1);
+        Some((
+            Rect::new(area.x1, area.y1, x - area.x1, height),
+            Rect::new(x, area.y1, area.x2 - x, height),
+        ))
+    } else {
+        let y = rng.range(area.y1 + min_leaf, area.y2 - min_leaf + 1);
+        Some((
+            Rect::new(area.x1, area.y1, width, y - area.y1),
+            Rect::new(area.x1, y, width, area.y2 - y),
+        ))
+    }
+}
+
+/// A room of random size and place inside a leaf, with wall left on every
+/// side.
+fn leaf_room(area: &Rect, rng: &mut RandomNumberGenerator) -> Rect {
+    let width = rng.range(MIN_ROOM, area.x2 - area.x1 - 1);
+    let height = rng.range(MIN_ROOM, area.y2 - area.y1 - 1);
+    let x = rng.range(area.x1, area.x2 - width);
+    let y = rng.range(area.y1, area.y2 - height);
+    Rect::new(x, y, width, height)
+}
+
+/// Digs a corridor between the two rooms, one from each side, that are
+/// closest together.
+fn join_closest(map: &mut Map, first: &[Rect], second: &[Rect], rng: &mut RandomNumberGenerator) {
+    let closest = first
+        .iter()
+        .flat_map(|a| second.iter().map(move |b| (a.center(), b.center())))
+        .min_by_key(|((ax, ay), (bx, by))| (ax - bx).abs() + (ay - by).abs());
+    if let Some((from, to)) = closest {
+        map.tunnel_between(from, to, rng);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_check::playable_problems;
+
+    #[test]
+    fn seeded_bsp_levels_are_playable() {
+        for seed in 0..5 {
+            let mut rng = RandomNumberGenerator::seeded(seed);
+            let mut map = build(1, &mut rng);
+            let problems = playable_problems(&mut map);
+            assert!(
+                problems.is_empty(),
+                "seed {}: {}",
+                seed,
+                problems.join("; ")
+            );
+        }
+    }
+
+    #[test]
+    fn seeded_bsp_interiors_are_playable() {
+        for seed in 0..5 {
+            let mut rng = RandomNumberGenerator::seeded(seed);
+            let mut map = build_interior(1, &mut rng);
+            let problems = playable_problems(&mut map);
+            assert!(
+                problems.is_empty(),
+                "seed {}: {}",
+                seed,
+                problems.join("; ")
+            );
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use super::{Map, TileType};
+use rltk::{DijkstraMap, DistanceAlg, Point, RandomNumberGenerator};
+
+/// Chance, in percent, that a tile of the starting noise is wall.
+const WALL_PERCENT: i32 = 55;
+const SMOOTHING_PASSES: i32 = 12;
+/// Roughly how many floor tiles go into each spawn region.
+const TILES_PER_REGION: usize = 120;
+
+/// Builds a cave: random noise smoothed into caverns, cut down to the
+/// largest connected one.
+pub fn build(depth: i32, rng: &mut RandomNumberGenerator) -> Map {
+    let mut map = Map::new(depth);
+
+    for y in 1..map.height - 1 {
+        for x in 1..map.width - 1 {
+            let index = map.get_index_xy(x, y);
+            map.tiles[index] = if rng.roll_dice(1, 100) > WALL_PERCENT {
+                TileType::Floor
+            } else {
+                TileType::Wall
+            };
+        }
+    }
+    map.take_snapshot();
+
+    for _pass in 0..SMOOTHING_PASSES {
+        smooth(&mut map);
+        map.take_snapshot();
+    }
+    finish_caverns(&mut map, rng);
+    map
+}
+
+/// Cuts an open level down to its largest cavern, then starts the player
+/// near the middle with the stairs down as far away as the level allows.
+/// There are no rooms, so the floor is split into Voronoi spawn regions.
+pub fn finish_caverns(map: &mut Map, rng: &mut RandomNumberGenerator) {
+    keep_largest_cavern(map);
+    map.take_snapshot();
+
+    let center = Point::new(map.width / 2, map.height / 2);
+    let start = match nearest_floor(map, center) {
+        Some(start) => start,
+        // Nothing is left; the level gets rejected
+        None => return,
+    };
+    let (start_x, start_y) = (start as i32 % map.width, start as i32 / map.width);
+    map.mark_start(start_x, start_y);
+
+    map.populate_blocked();
+    let max_depth = (map.width * map.height) as f32;
+    let dijkstra_map = DijkstraMap::new(map.width, map.height, &[start], &*map, max_depth);
+    let farthest = dijkstra_map
+        .map
+        .iter()
+        .enumerate()
+        .filter(|(_index, distance)| **distance < f32::MAX)
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).expect("Distances are never NaN"))
+        .map(|(index, _distance)| index);
+    if let Some(stairs) = farthest {
+        map.place_stairs(stairs);
+    }
+
+    map.spawn_regions = voronoi_regions(map, start, rng);
+    map.take_snapshot();
+}
+
+/// Tiles with mostly wall around them become wall, the rest floor. Lone
+/// tiles with no wall at all around them become wall too, which breaks up
+/// wide open areas into pillars.
+fn smooth(map: &mut Map) {
+    let mut tiles = map.tiles.clone();
+    for y in 1..map.height - 1 {
+        for x in 1..map.width - 1 {
+            let walls = neighbours(map, x, y)
+                .filter(|index| map.tiles[*index] == TileType::Wall)
+                .count();
+            let index = map.get_index_xy(x, y);
+            tiles[index] = if walls > 4 || walls == 0 {
+                TileType::Wall
+            } else {
+                TileType::Floor
+            };
+        }
+    }
+    map.tiles = tiles;
+}
+
+/// The eight tiles around `x`, `y` that are on the map.
+fn neighbours(map: &Map, x: i32, y: i32) -> impl Iterator<Item = usize> + '_ {
+    (-1..=1)
+        .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+        .filter(|(dx, dy)| *dx != 0 || *dy != 0)
+        .map(move |(dx, dy)| (x + dx, y + dy))
+        .filter(move |(x, y)| *x >= 0 && *x < map.width && *y >= 0 && *y < map.height)
+        .map(move |(x, y)| map.get_index_xy(x, y))
+}
+
+/// Walls up every cavern but the biggest one. Caverns are joined the way
+/// the player moves, diagonals included.
+fn keep_largest_cavern(map: &mut Map) {
+    let mut cavern_of: Vec<Option<usize>> = vec![None; map.tiles.len()];
+    let mut sizes: Vec<usize> = Vec::new();
+
+    for first in 0..map.tiles.len() {
+        if map.tiles[first] != TileType::Floor || cavern_of[first].is_some() {
+            continue;
+        }
+        let cavern = sizes.len();
+        let mut size = 0;
+        let mut open = vec![first];
+        cavern_of[first] = Some(cavern);
+        while let Some(index) = open.pop() {
+            size += 1;
+            let (x, y) = (index as i32 % map.width, index as i32 / map.width);
+            for next in neighbours(map, x, y) {
+                if map.tiles[next] == TileType::Floor && cavern_of[next].is_none() {
+                    cavern_of[next] = Some(cavern);
+                    open.push(next);
+                }
+            }
+        }
+        sizes.push(size);
+    }
+
+    let largest = sizes
+        .iter()
+        .enumerate()
+        .max_by_key(|(_cavern, size)| **size)
+        .map(|(cavern, _size)| cavern);
+    for (tile, cavern) in map.tiles.iter_mut().zip(cavern_of.iter()) {
+        if cavern.is_some() && *cavern != largest {
+            *tile = TileType::Wall;
+        }
+    }
+}
+
+fn nearest_floor(map: &Map, target: Point) -> Option<usize> {
+    map.tiles
+        .iter()
+        .enumerate()
+        .filter(|(_index, tile)| **tile == TileType::Floor)
+        .map(|(index, _tile)| index)
+        .min_by_key(|index| {
+            let x = *index as i32 % map.width;
+            let y = *index as i32 / map.width;
+            (x - target.x).abs() + (y - target.y).abs()
+        })
+}
+
+/// Scatters seeds over the floor and gives every floor tile to the closest
+/// one. The region the player starts in is left out, like the start room.
+fn voronoi_regions(map: &Map, start: usize, rng: &mut RandomNumberGenerator) -> Vec<Vec<usize>> {
+    let floor: Vec<usize> = map
+        .tiles
+        .iter()
+        .enumerate()
+        .filter(|(_index, tile)| **tile == TileType::Floor)
+        .map(|(index, _tile)| index)
+        .collect();
+    let seed_count = (floor.len() / TILES_PER_REGION).max(1);
+    let mut seeds: Vec<Point> = Vec::new();
+    while seeds.len() < seed_count.min(floor.len()) {
+        if let Some(index) = rng.random_slice_entry(&floor) {
+            let seed = Point::new(*index as i32 % map.width, *index as i32 / map.width);
+            if !seeds.contains(&seed) {
+                seeds.push(seed);
+            }
+        }
+    }
+
+    let mut regions: Vec<Vec<usize>> = vec![Vec::new(); seeds.len()];
+    let mut start_region = None;
+    for index in floor {
+        let tile = Point::new(index as i32 % map.width, index as i32 / map.width);
+        let closest = seeds
+            .iter()
+            .enumerate()
+            .map(|(region, seed)| (region, DistanceAlg::Pythagoras.distance2d(tile, *seed)))
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).expect("Distances are never NaN"))
+            .map(|(region, _distance)| region);
+        if let Some(region) = closest {
+            if index == start {
+                start_region = Some(region);
+            }
+            regions[region].push(index);
+        }
+    }
+
+    if let Some(start_region) = start_region {
+        regions.remove(start_region);
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_check::playable_problems;
+
+    #[test]
+    fn seeded_caves_are_playable() {
+        for seed in 0..5 {
+            let mut rng = RandomNumberGenerator::seeded(seed);
+            let mut map = build(1, &mut rng);
+            let problems = playable_problems(&mut map);
+            assert!(
+                problems.is_empty(),
+                "seed {}: {}",
+                seed,
+                problems.join("; ")
+            );
+        }
+    }
+}
@@ -19,6 +19,13 @@ pub struct Renderable {
 #[derive(Component)]
 pub struct LeftMover {}
 
+/// Lights the tiles it can see, fading out towards `range`.
+#[derive(Component)]
+pub struct LightSource {
+    pub color: RGB,
+    pub range: i32,
+}
+
 #[derive(Component, Debug)]
 pub struct Player {}
 
@@ -32,6 +39,26 @@ pub struct Viewshed {
 #[derive(Component, Debug)]
 pub struct Monster {}
 
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum NpcBehavior {
+    StayPut,
+    Wander,
+}
+
+/// A friendly character. Bumping into one talks to it instead of attacking.
+#[derive(Component, Debug)]
+pub struct Npc {
+    pub behavior: NpcBehavior,
+}
+
+/// Sells what it carries, each item at its `Price`.
+#[derive(Component, Debug)]
+pub struct Shopkeeper {}
+
+/// Heals the player back to full health when they talk.
+#[derive(Component, Debug)]
+pub struct Healer {}
+
 #[derive(Component, Debug)]
 pub struct Name {
     pub name: String,
@@ -53,14 +80,50 @@ pub struct WantsToMelee {
     pub target: Entity,
 }
 
+#[derive(Component, Debug, Clone)]
+pub struct WantsToTalk {
+    pub npc: Entity,
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct WantsToBuy {
+    pub item: Entity,
+}
+
 #[derive(Component, Debug)]
 pub struct Item {}
 
+#[derive(Component, Debug)]
+pub struct Corpse {}
+
+/// The gold someone carries.
+#[derive(Component, Debug)]
+pub struct Purse {
+    pub gold: i32,
+}
+
+/// What an item costs to buy from a shopkeeper.
+#[derive(Component, Debug)]
+pub struct Price {
+    pub gold: i32,
+}
+
+/// What a monster may leave behind when it dies. Each entry is an item name
+/// with its relative weight; `nothing_weight` is the weight of no drop.
+#[derive(Component, Debug, Clone)]
+pub struct LootTable {
+    pub nothing_weight: i32,
+    pub drops: Vec<(String, i32)>,
+}
+
 #[derive(Component, Debug)]
 pub struct Potion {
     pub heal_amount: i32,
 }
 
+#[derive(Component, Debug)]
+pub struct Key {}
+
 #[derive(Component, Debug, Clone)]
 pub struct InBackpack {
     pub owner: Entity,
@@ -82,18 +145,71 @@ pub struct WantsToDropItem {
     pub item: Entity,
 }
 
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum TrapKind {
+    Spike { damage: i32 },
+    Teleport,
+    Alarm,
+}
+
+#[derive(Component, Debug)]
+pub struct Trap {
+    pub kind: TrapKind,
+}
+
+#[derive(Component, Debug)]
+pub struct Hidden {}
+
+#[derive(Component, Debug)]
+pub struct EntityMoved {}
+
+/// Set on whatever just moved onto slow ground. Its next turn is spent
+/// getting clear.
+#[derive(Component, Debug)]
+pub struct Wading {}
+
+/// Set on whatever the player hurt last, so its death counts as their kill.
+/// Damage from anything else takes it away again.
+#[derive(Component, Debug)]
+pub struct LastHitByPlayer {}
+
+/// Can cross deep water, which stops everything else.
+#[derive(Component, Debug)]
+pub struct Swimmer {}
+
+#[derive(Component, Debug)]
+pub struct Alerted {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Component, Debug)]
+pub struct ParticleLifetime {
+    pub lifetime_ms: f32,
+}
+
+/// Damage to apply this turn. `from_player` is set when any of it was dealt
+/// by the player.
 #[derive(Component, Debug)]
 pub struct SufferDamage {
     pub amount: Vec<i32>,
+    pub from_player: bool,
 }
 
 impl SufferDamage {
-    pub fn new_damage(store: &mut WriteStorage<SufferDamage>, victim: Entity, amount: i32) {
+    pub fn new_damage(
+        store: &mut WriteStorage<SufferDamage>,
+        victim: Entity,
+        amount: i32,
+        from_player: bool,
+    ) {
         if let Some(suffering) = store.get_mut(victim) {
             suffering.amount.push(amount);
+            suffering.from_player |= from_player;
         } else {
             let damage = SufferDamage {
                 amount: vec![amount],
+                from_player,
             };
             store
                 .insert(victim, damage)
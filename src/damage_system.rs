@@ -1,19 +1,62 @@
-use super::{CombatStats, GameLog, Name, Player, SufferDamage};
+use super::{
+    CombatStats, Decoration, GameLog, LastHitByPlayer, LootTable, Map, Name, Player, Position,
+    RunState, SufferDamage,
+};
+use crate::entity_cleanup;
+use crate::highscores::{self, HighScores, ScoreEntry};
+use crate::morgue;
+use crate::replay;
+use crate::run_record::RunRecord;
+use crate::spawner;
+use crate::wizard::Wizard;
 use specs::prelude::*;
 
 pub struct DamageSystem {}
 
 impl<'a> System<'a> for DamageSystem {
     type SystemData = (
+        Entities<'a>,
         WriteStorage<'a, CombatStats>,
         WriteStorage<'a, SufferDamage>,
+        ReadStorage<'a, Position>,
+        WriteExpect<'a, Map>,
+        ReadExpect<'a, Entity>,
+        ReadExpect<'a, Wizard>,
+        WriteStorage<'a, LastHitByPlayer>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut stats, mut damage) = data;
+        let (
+            entities,
+            mut stats,
+            mut damage,
+            positions,
+            mut map,
+            player_entity,
+            wizard,
+            mut last_hit_by_player,
+        ) = data;
 
-        for (mut stats, damage) in (&mut stats, &damage).join() {
-            stats.hp -= damage.amount.iter().sum::<i32>();
+        for (entity, stats, damage) in (&entities, &mut stats, &damage).join() {
+            if wizard.god_mode && entity == *player_entity {
+                continue;
+            }
+            let amount = damage.amount.iter().sum::<i32>();
+            stats.hp -= amount;
+
+            if amount > 0 {
+                if damage.from_player {
+                    last_hit_by_player
+                        .insert(entity, LastHitByPlayer {})
+                        .expect("Unable to insert marker");
+                } else {
+                    last_hit_by_player.remove(entity);
+                }
+                if let Some(pos) = positions.get(entity) {
+                    let index = map.get_index_xy(pos.x, pos.y);
+                    map.add_decoration(index, Decoration::Bloodstain);
+                }
+            }
         }
 
         damage.clear()
@@ -22,6 +65,7 @@ impl<'a> System<'a> for DamageSystem {
 
 pub fn delete_the_dead(ecs: &mut World) {
     let mut dead: Vec<Entity> = Vec::new();
+    let mut player_died = false;
     // Using a scope to make the borrow checker happy
     {
         let combat_stats = ecs.read_storage::<CombatStats>();
@@ -29,6 +73,7 @@ pub fn delete_the_dead(ecs: &mut World) {
         let names = ecs.read_component::<Name>();
         let entities = ecs.entities();
         let mut log = ecs.write_resource::<GameLog>();
+        let run_state = ecs.fetch::<RunState>();
 
         for (entity, stats) in (&entities, &combat_stats).join() {
             if stats.hp < 1 {
@@ -41,13 +86,93 @@ pub fn delete_the_dead(ecs: &mut World) {
                         }
                         dead.push(entity)
                     }
-                    Some(_) => log.entries.push("Your are dead!".to_string()),
+                    Some(_) => {
+                        if *run_state != RunState::GameOver {
+                            log.entries.push("You are dead!".to_string());
+                            player_died = true;
+                        }
+                    }
                 }
             }
         }
     }
 
+    if player_died {
+        let morgue_file = match morgue::write_morgue(ecs) {
+            Ok(path) => Some(path),
+            Err(error) => {
+                eprintln!("Unable to write the morgue file: {}", error);
+                None
+            }
+        };
+        ecs.write_resource::<RunRecord>().morgue_file = morgue_file;
+        // A replayed run is already on the high-score table, and wizard runs
+        // do not belong there
+        let replaying = replay::is_playing(ecs);
+        replay::finish(ecs);
+        if !replaying && !ecs.fetch::<RunRecord>().wizard {
+            record_high_score(ecs);
+        }
+        *ecs.write_resource::<RunState>() = RunState::GameOver;
+    }
+
     for victim in dead {
+        let (position, name, loot) = {
+            let positions = ecs.read_storage::<Position>();
+            let names = ecs.read_storage::<Name>();
+            let loot_tables = ecs.read_storage::<LootTable>();
+            let combat_stats = ecs.read_storage::<CombatStats>();
+            let mut record = ecs.write_resource::<RunRecord>();
+            // Deaths to traps, lava or other monsters are not the player's
+            if ecs.read_storage::<LastHitByPlayer>().contains(victim) {
+                record.kills += 1;
+                record.xp += combat_stats.get(victim).map_or(0, |stats| stats.max_hp);
+            }
+            (
+                positions.get(victim).map(|pos| (pos.x, pos.y)),
+                names.get(victim).map(|name| name.name.clone()),
+                loot_tables.get(victim).cloned(),
+            )
+        };
+
+        if let Some((x, y)) = position {
+            // Leave the body and whatever the loot table rolls on the floor
+            spawner::corpse(ecs, x, y, name.as_deref().unwrap_or("Unknown"));
+            if let Some(loot) = loot {
+                if let Some(item) = spawner::roll_loot(ecs, &loot) {
+                    spawner::spawn_named_item(ecs, &item, x, y);
+                }
+            }
+
+            let mut map = ecs.fetch_mut::<Map>();
+            let index = map.get_index_xy(x, y);
+            map.add_decoration(index, Decoration::Corpse);
+        }
+        entity_cleanup::release_references(ecs, victim);
         ecs.delete_entity(victim).expect("Unable to delete");
     }
+    entity_cleanup::remove_dangling_references(ecs);
+}
+
+/// Adds the finished run to the high-score table and saves it.
+fn record_high_score(ecs: &mut World) {
+    let entry = {
+        let record = ecs.fetch::<RunRecord>();
+        let map = ecs.fetch::<Map>();
+        ScoreEntry::new(
+            &record.character_name,
+            record.cause_of_death(),
+            map.depth,
+            record.kills,
+            record.xp,
+            record.turns,
+        )
+    };
+
+    let mut high_scores = ecs.write_resource::<HighScores>();
+    let place = high_scores.add(entry);
+    if let Err(error) = high_scores.save(highscores::HIGH_SCORE_FILE) {
+        eprintln!("Unable to save the high scores: {}", error);
+    }
+    ecs.write_resource::<RunRecord>().high_score_place = place;
 }
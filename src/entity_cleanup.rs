@@ -0,0 +1,165 @@
+use super::{
+    InBackpack, Position, WantsToBuy, WantsToDrinkPotion, WantsToDropItem, WantsToMelee,
+    WantsToPickupItem, WantsToTalk,
+};
+use specs::prelude::*;
+
+/// Gets the world ready for `victim` to be deleted: whatever it carries is
+/// dropped where it stood, and intents aimed at it are cancelled.
+pub fn release_references(ecs: &mut World, victim: Entity) {
+    let position = ecs
+        .read_storage::<Position>()
+        .get(victim)
+        .map(|pos| (pos.x, pos.y));
+
+    {
+        let entities = ecs.entities();
+        let mut backpack = ecs.write_storage::<InBackpack>();
+        let mut positions = ecs.write_storage::<Position>();
+
+        let carried: Vec<Entity> = (&entities, &backpack)
+            .join()
+            .filter(|(_item, pack)| pack.owner == victim)
+            .map(|(item, _pack)| item)
+            .collect();
+        for item in carried {
+            backpack.remove(item);
+            match position {
+                Some((x, y)) => {
+                    positions
+                        .insert(item, Position { x, y })
+                        .expect("Unable to insert position");
+                }
+                None => entities.delete(item).expect("Delete failed"),
+            }
+        }
+    }
+
+    cancel_intents(ecs, |target| target == victim);
+}
+
+/// Fixes up every reference to an entity that no longer exists. Items whose
+/// owner is gone have nowhere to be dropped, so they are deleted as well.
+pub fn remove_dangling_references(ecs: &mut World) {
+    let orphans: Vec<Entity> = {
+        let entities = ecs.entities();
+        let backpack = ecs.read_storage::<InBackpack>();
+        (&entities, &backpack)
+            .join()
+            .filter(|(_item, pack)| !entities.is_alive(pack.owner))
+            .map(|(item, _pack)| item)
+            .collect()
+    };
+    for orphan in orphans {
+        ecs.delete_entity(orphan).expect("Unable to delete");
+    }
+
+    let dead_targets: Vec<Entity> = {
+        let entities = ecs.entities();
+        all_references(ecs)
+            .into_iter()
+            .map(|(_component, _holder, target)| target)
+            .filter(|target| !entities.is_alive(*target))
+            .collect()
+    };
+    cancel_intents(ecs, |target| dead_targets.contains(&target));
+}
+
+/// Removes every intent component that points at an entity matching `is_gone`.
+fn cancel_intents<F: Fn(Entity) -> bool>(ecs: &mut World, is_gone: F) {
+    let entities = ecs.entities();
+    let mut wants_melee = ecs.write_storage::<WantsToMelee>();
+    let mut wants_pickup = ecs.write_storage::<WantsToPickupItem>();
+    let mut wants_drink = ecs.write_storage::<WantsToDrinkPotion>();
+    let mut wants_drop = ecs.write_storage::<WantsToDropItem>();
+    let mut wants_talk = ecs.write_storage::<WantsToTalk>();
+    let mut wants_buy = ecs.write_storage::<WantsToBuy>();
+
+    for entity in entities.join() {
+        if wants_melee.get(entity).is_some_and(|w| is_gone(w.target)) {
+            wants_melee.remove(entity);
+        }
+        if wants_pickup
+            .get(entity)
+            .is_some_and(|w| is_gone(w.item) || is_gone(w.collected_by))
+        {
+            wants_pickup.remove(entity);
+        }
+        if wants_drink.get(entity).is_some_and(|w| is_gone(w.potion)) {
+            wants_drink.remove(entity);
+        }
+        if wants_drop.get(entity).is_some_and(|w| is_gone(w.item)) {
+            wants_drop.remove(entity);
+        }
+        if wants_talk.get(entity).is_some_and(|w| is_gone(w.npc)) {
+            wants_talk.remove(entity);
+        }
+        if wants_buy.get(entity).is_some_and(|w| is_gone(w.item)) {
+            wants_buy.remove(entity);
+        }
+    }
+}
+
+/// Every entity reference held by a component, as (component, holder, target).
+fn all_references(ecs: &World) -> Vec<(&'static str, Entity, Entity)> {
+    let entities = ecs.entities();
+    let backpack = ecs.read_storage::<InBackpack>();
+    let wants_melee = ecs.read_storage::<WantsToMelee>();
+    let wants_pickup = ecs.read_storage::<WantsToPickupItem>();
+    let wants_drink = ecs.read_storage::<WantsToDrinkPotion>();
+    let wants_drop = ecs.read_storage::<WantsToDropItem>();
+    let wants_talk = ecs.read_storage::<WantsToTalk>();
+    let wants_buy = ecs.read_storage::<WantsToBuy>();
+
+    let mut references = Vec::new();
+    for (entity, pack) in (&entities, &backpack).join() {
+        references.push(("InBackpack.owner", entity, pack.owner));
+    }
+    for (entity, melee) in (&entities, &wants_melee).join() {
+        references.push(("WantsToMelee.target", entity, melee.target));
+    }
+    for (entity, pickup) in (&entities, &wants_pickup).join() {
+        references.push(("WantsToPickupItem.item", entity, pickup.item));
+        references.push((
+            "WantsToPickupItem.collected_by",
+            entity,
+            pickup.collected_by,
+        ));
+    }
+    for (entity, drink) in (&entities, &wants_drink).join() {
+        references.push(("WantsToDrinkPotion.potion", entity, drink.potion));
+    }
+    for (entity, drop) in (&entities, &wants_drop).join() {
+        references.push(("WantsToDropItem.item", entity, drop.item));
+    }
+    for (entity, talk) in (&entities, &wants_talk).join() {
+        references.push(("WantsToTalk.npc", entity, talk.npc));
+    }
+    for (entity, buy) in (&entities, &wants_buy).join() {
+        references.push(("WantsToBuy.item", entity, buy.item));
+    }
+    references
+}
+
+/// Debug builds only: panics if any component still points at a deleted
+/// entity. Meant to run after `remove_dangling_references`, so anything it
+/// finds is a reference the cleanup does not know about.
+#[cfg(debug_assertions)]
+pub fn check_entity_references(ecs: &World) {
+    let entities = ecs.entities();
+    let dangling: Vec<String> = all_references(ecs)
+        .into_iter()
+        .filter(|(_component, _holder, target)| !entities.is_alive(*target))
+        .map(|(component, holder, target)| {
+            format!(
+                "{} on {:?} points at deleted {:?}",
+                component, holder, target
+            )
+        })
+        .collect();
+    assert!(
+        dangling.is_empty(),
+        "Dangling entity references: {}",
+        dangling.join("; ")
+    );
+}
@@ -1,4 +1,9 @@
-use super::{CombatStats, GameLog, InBackpack, Map, Name, Player, Position, State};
+use super::keybindings::{key_name, KeyBindings};
+use super::wizard;
+use super::{
+    CombatStats, GameLog, Hidden, HighScores, InBackpack, Map, Monster, Name, Player, Position,
+    Price, Purse, RunRecord, RunState, State,
+};
 use rltk::{Point, Rltk, VirtualKeyCode, RGB};
 use specs::prelude::*;
 
@@ -34,13 +39,21 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
         );
     }
 
+    let purses = ecs.read_storage::<Purse>();
+    for (_player, purse) in (&players, &purses).join() {
+        let gold = format!("${}", purse.gold);
+        ctx.print_color(
+            2,
+            43,
+            RGB::named(rltk::GOLD),
+            RGB::named(rltk::BLACK),
+            &gold,
+        );
+    }
+
     let log = ecs.fetch::<GameLog>();
-    let mut y = 44;
-    for s in log.entries.iter().rev() {
-        if y < 49 {
-            ctx.print(2, y, s);
-        }
-        y += 1;
+    for (y, s) in (44..49).zip(log.entries.iter().rev()) {
+        ctx.print(2, y, s);
     }
     // Draw mouse cursor
     let mouse_pos = ctx.mouse_pos();
@@ -52,13 +65,23 @@ fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
     let map = ecs.fetch::<Map>();
     let names = ecs.read_storage::<Name>();
     let positions = ecs.read_storage::<Position>();
+    let hidden = ecs.read_storage::<Hidden>();
+    let monsters = ecs.read_storage::<Monster>();
 
+    let player_pos = *ecs.fetch::<Point>();
     let mouse_pos = ctx.mouse_pos();
     if mouse_pos.0 < map.width || mouse_pos.1 < map.height {
         let mut tooltip: Vec<String> = Vec::new();
-        for (name, position) in (&names, &positions).join() {
+        for (name, position, _hidden, monster) in
+            (&names, &positions, !&hidden, monsters.maybe()).join()
+        {
             let index = map.get_index_xy(position.x, position.y);
-            if position.x == mouse_pos.0 && position.y == mouse_pos.1 && map.visible_tiles[index] {
+            let seen = if monster.is_some() {
+                map.can_spot(index, player_pos)
+            } else {
+                map.visible_tiles[index]
+            };
+            if position.x == mouse_pos.0 && position.y == mouse_pos.1 && seen {
                 tooltip.push(name.name.to_string());
             }
         }
@@ -75,9 +98,7 @@ fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
             if mouse_pos.0 > 40 {
                 let arrow_pos = Point::new(mouse_pos.0 - 2, mouse_pos.1);
                 let left_x = mouse_pos.0 - width;
-                let mut y = mouse_pos.1;
-
-                for s in tooltip.iter() {
+                for (y, s) in (mouse_pos.1..).zip(tooltip.iter()) {
                     ctx.print_color(
                         left_x,
                         y,
@@ -92,24 +113,21 @@ fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
                             y,
                             RGB::named(rltk::WHITE),
                             RGB::named(rltk::GREY),
-                            &" ".to_string(),
+                            " ",
                         );
                     }
-                    y += 1;
                 }
                 ctx.print_color(
                     arrow_pos.x,
                     arrow_pos.y,
                     RGB::named(rltk::WHITE),
                     RGB::named(rltk::GREY),
-                    &"->".to_string(),
+                    "->",
                 );
             } else {
                 let arrow_pos = Point::new(mouse_pos.0 + 1, mouse_pos.1);
                 let left_x = mouse_pos.0 + 3;
-                let mut y = mouse_pos.1;
-
-                for s in tooltip.iter() {
+                for (y, s) in (mouse_pos.1..).zip(tooltip.iter()) {
                     ctx.print_color(
                         left_x + 1,
                         y,
@@ -124,17 +142,16 @@ fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
                             y,
                             RGB::named(rltk::WHITE),
                             RGB::named(rltk::GREY),
-                            &" ".to_string(),
+                            " ",
                         );
                     }
-                    y += 1;
                 }
                 ctx.print_color(
                     arrow_pos.x,
                     arrow_pos.y,
                     RGB::named(rltk::WHITE),
                     RGB::named(rltk::GREY),
-                    &"<-".to_string(),
+                    "<-",
                 );
             }
         }
@@ -159,7 +176,7 @@ pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
         .filter(|item| item.0.owner == *player_entity);
     let count = inventory.count();
 
-    let mut y = (25 - (count / 2)) as i32;
+    let y = (25 - (count / 2)) as i32;
     ctx.draw_box(
         15,
         y - 2,
@@ -189,6 +206,7 @@ pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
         .filter(|item| item.1.owner == *player_entity)
         .enumerate()
     {
+        let y = y + j as i32;
         ctx.set(
             17,
             y,
@@ -211,9 +229,8 @@ pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
             rltk::to_cp437(')'),
         );
 
-        ctx.print(21, y, &name.name.to_string());
+        ctx.print(21, y, &name.name);
         equippable.push(entity);
-        y += 1;
     }
     match ctx.key {
         None => (ItemMenuResult::NoResponse, None),
@@ -244,7 +261,7 @@ pub fn drop_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
         .filter(|item| item.0.owner == *player_entity);
     let count = inventory.count();
 
-    let mut y = (25 - (count / 2)) as i32;
+    let y = (25 - (count / 2)) as i32;
     ctx.draw_box(
         15,
         y - 2,
@@ -274,6 +291,7 @@ pub fn drop_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
         .filter(|item| item.1.owner == *player_entity)
         .enumerate()
     {
+        let y = y + j as i32;
         ctx.set(
             17,
             y,
@@ -296,9 +314,8 @@ pub fn drop_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
             rltk::to_cp437(')'),
         );
 
-        ctx.print(21, y, &name.name.to_string());
+        ctx.print(21, y, &name.name);
         equippable.push(entity);
-        y += 1;
     }
 
     match ctx.key {
@@ -318,3 +335,379 @@ pub fn drop_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
         },
     }
 }
+
+/// Lists what `shopkeeper` has for sale, with the price of each item.
+pub fn shop_menu(
+    gs: &mut State,
+    ctx: &mut Rltk,
+    shopkeeper: Entity,
+) -> (ItemMenuResult, Option<Entity>) {
+    let names = gs.ecs.read_storage::<Name>();
+    let backpack = gs.ecs.read_storage::<InBackpack>();
+    let prices = gs.ecs.read_storage::<Price>();
+    let entities = gs.ecs.entities();
+
+    let stock: Vec<(Entity, String)> = (&entities, &backpack, &names)
+        .join()
+        .filter(|item| item.1.owner == shopkeeper)
+        .map(|(entity, _pack, name)| {
+            let price = prices.get(entity).map_or(0, |price| price.gold);
+            (entity, format!("{} ({} gold)", name.name, price))
+        })
+        .collect();
+    let count = stock.len();
+
+    let y = (25 - (count / 2)) as i32;
+    ctx.draw_box(
+        15,
+        y - 2,
+        31,
+        (count + 3) as i32,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+    );
+    ctx.print_color(
+        18,
+        y - 2,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "Buy Which Item?",
+    );
+    ctx.print_color(
+        18,
+        y + count as i32 + 1,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "ESCAPE to cancel",
+    );
+
+    for (j, (_entity, label)) in stock.iter().enumerate() {
+        let y = y + j as i32;
+        ctx.set(
+            17,
+            y,
+            RGB::named(rltk::WHITE),
+            RGB::named(rltk::BLACK),
+            rltk::to_cp437('('),
+        );
+        ctx.set(
+            18,
+            y,
+            RGB::named(rltk::YELLOW),
+            RGB::named(rltk::BLACK),
+            97 + j as rltk::FontCharType,
+        );
+        ctx.set(
+            19,
+            y,
+            RGB::named(rltk::WHITE),
+            RGB::named(rltk::BLACK),
+            rltk::to_cp437(')'),
+        );
+
+        ctx.print(21, y, label);
+    }
+
+    match ctx.key {
+        None => (ItemMenuResult::NoResponse, None),
+        Some(key) => match key {
+            VirtualKeyCode::Escape => (ItemMenuResult::Cancel, None),
+            _ => {
+                let selection = rltk::letter_to_option(key);
+                if selection > -1 && selection < count as i32 {
+                    return (ItemMenuResult::Selected, Some(stock[selection as usize].0));
+                }
+                (ItemMenuResult::NoResponse, None)
+            }
+        },
+    }
+}
+
+pub fn show_help(gs: &mut State, ctx: &mut Rltk) -> ItemMenuResult {
+    let key_bindings = gs.ecs.fetch::<KeyBindings>();
+    let count = key_bindings.bindings.len() as i32;
+
+    let y = 25 - (count / 2);
+    ctx.draw_box(
+        10,
+        y - 2,
+        59,
+        count + 4,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+    );
+    ctx.print_color(
+        13,
+        y - 2,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "Key Bindings",
+    );
+    ctx.print_color(
+        12,
+        y + count,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+        "Hold SHIFT with a move key to run, click a tile to travel",
+    );
+    ctx.print_color(
+        13,
+        y + count + 2,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "ESCAPE to close",
+    );
+
+    for (j, (action, keys)) in key_bindings.bindings.iter().enumerate() {
+        let y = y + j as i32;
+        let keys = keys
+            .iter()
+            .map(|key| key_name(*key))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        ctx.print_color(
+            12,
+            y,
+            RGB::named(rltk::WHITE),
+            RGB::named(rltk::BLACK),
+            action.description(),
+        );
+        ctx.print_color(
+            32,
+            y,
+            RGB::named(rltk::YELLOW),
+            RGB::named(rltk::BLACK),
+            &keys,
+        );
+    }
+
+    match ctx.key {
+        Some(VirtualKeyCode::Escape) => ItemMenuResult::Cancel,
+        _ => ItemMenuResult::NoResponse,
+    }
+}
+
+/// Draws the wizard console over the bottom of the map.
+pub fn draw_wizard_console(ecs: &World, ctx: &mut Rltk) {
+    let (output, prompt) = wizard::console_lines(ecs);
+
+    ctx.draw_box(
+        0,
+        36,
+        79,
+        6,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+    );
+    ctx.print_color(
+        2,
+        36,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "Wizard Console",
+    );
+    for (y, line) in (37..).zip(output.iter()) {
+        ctx.print_color(2, y, RGB::named(rltk::GREY), RGB::named(rltk::BLACK), line);
+    }
+    ctx.print_color(
+        2,
+        41,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+        &prompt,
+    );
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum MainMenuSelection {
+    NewGame,
+    HighScores,
+    Quit,
+}
+
+impl MainMenuSelection {
+    const ALL: [MainMenuSelection; 3] = [
+        MainMenuSelection::NewGame,
+        MainMenuSelection::HighScores,
+        MainMenuSelection::Quit,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            MainMenuSelection::NewGame => "Begin New Game",
+            MainMenuSelection::HighScores => "High Scores",
+            MainMenuSelection::Quit => "Quit",
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum MainMenuResult {
+    NoSelection { selected: MainMenuSelection },
+    Selected { selected: MainMenuSelection },
+}
+
+pub fn main_menu(gs: &mut State, ctx: &mut Rltk) -> MainMenuResult {
+    let selection = match *gs.ecs.fetch::<RunState>() {
+        RunState::MainMenu { menu_selection } => menu_selection,
+        _ => MainMenuSelection::NewGame,
+    };
+
+    ctx.print_color_centered(
+        15,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "Rusty Roguelike",
+    );
+    for (y, option) in (24..).step_by(2).zip(MainMenuSelection::ALL.iter()) {
+        let fg = if *option == selection {
+            RGB::named(rltk::MAGENTA)
+        } else {
+            RGB::named(rltk::WHITE)
+        };
+        ctx.print_color_centered(y, fg, RGB::named(rltk::BLACK), option.label());
+    }
+
+    let index = MainMenuSelection::ALL
+        .iter()
+        .position(|option| *option == selection)
+        .unwrap_or(0);
+    let count = MainMenuSelection::ALL.len();
+    match ctx.key {
+        Some(VirtualKeyCode::Escape) => MainMenuResult::NoSelection {
+            selected: MainMenuSelection::Quit,
+        },
+        Some(VirtualKeyCode::Up) => MainMenuResult::NoSelection {
+            selected: MainMenuSelection::ALL[(index + count - 1) % count],
+        },
+        Some(VirtualKeyCode::Down) => MainMenuResult::NoSelection {
+            selected: MainMenuSelection::ALL[(index + 1) % count],
+        },
+        Some(VirtualKeyCode::Return) => MainMenuResult::Selected {
+            selected: selection,
+        },
+        _ => MainMenuResult::NoSelection {
+            selected: selection,
+        },
+    }
+}
+
+/// Draws the high-score table with its header at `y`.
+fn draw_high_scores(ecs: &World, ctx: &mut Rltk, y: i32, highlight: Option<usize>) {
+    let high_scores = ecs.fetch::<HighScores>();
+
+    ctx.print_color_centered(
+        y,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "High Scores",
+    );
+    if high_scores.entries.is_empty() {
+        ctx.print_color_centered(
+            y + 2,
+            RGB::named(rltk::GREY),
+            RGB::named(rltk::BLACK),
+            "No runs recorded yet",
+        );
+        return;
+    }
+
+    ctx.print_color(
+        2,
+        y + 2,
+        RGB::named(rltk::GREY),
+        RGB::named(rltk::BLACK),
+        "   Score Depth Kills   XP Turns Name         Date       Cause of death",
+    );
+    for (j, entry) in high_scores.entries.iter().enumerate() {
+        let fg = if highlight == Some(j) {
+            RGB::named(rltk::MAGENTA)
+        } else {
+            RGB::named(rltk::WHITE)
+        };
+        let mut name = entry.name.clone();
+        name.truncate(12);
+        let line = format!(
+            "{:>2} {:>5} {:>5} {:>5} {:>4} {:>5} {:<12} {} {}",
+            j + 1,
+            entry.score,
+            entry.depth,
+            entry.kills,
+            entry.xp,
+            entry.turns,
+            name,
+            entry.date,
+            entry.cause
+        );
+        ctx.print_color(2, y + 3 + j as i32, fg, RGB::named(rltk::BLACK), &line);
+    }
+}
+
+pub fn show_high_scores(gs: &mut State, ctx: &mut Rltk) -> ItemMenuResult {
+    draw_high_scores(&gs.ecs, ctx, 15, None);
+    ctx.print_color_centered(
+        45,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "ESCAPE to return to the menu",
+    );
+
+    match ctx.key {
+        Some(VirtualKeyCode::Escape) => ItemMenuResult::Cancel,
+        _ => ItemMenuResult::NoResponse,
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum GameOverResult {
+    NoSelection,
+    MainMenu,
+}
+
+pub fn game_over(gs: &mut State, ctx: &mut Rltk) -> GameOverResult {
+    let record = gs.ecs.fetch::<RunRecord>();
+    let map = gs.ecs.fetch::<Map>();
+    let cause = format!("Killed by {}", record.cause_of_death());
+    let summary = format!(
+        "on depth {} after {} turns, with {} kills",
+        map.depth, record.turns, record.kills
+    );
+
+    ctx.print_color_centered(
+        4,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "Your journey has ended!",
+    );
+    ctx.print_color_centered(6, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), &cause);
+    ctx.print_color_centered(
+        7,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+        &summary,
+    );
+    let morgue_message = match &record.morgue_file {
+        Some(path) => format!("Morgue file written to {}", path),
+        None => "The morgue file could not be written".to_string(),
+    };
+    ctx.print_color_centered(
+        9,
+        RGB::named(rltk::GREY),
+        RGB::named(rltk::BLACK),
+        &morgue_message,
+    );
+
+    draw_high_scores(&gs.ecs, ctx, 12, record.high_score_place);
+    ctx.print_color_centered(
+        45,
+        RGB::named(rltk::MAGENTA),
+        RGB::named(rltk::BLACK),
+        "Press ESCAPE to return to the menu",
+    );
+
+    match ctx.key {
+        Some(VirtualKeyCode::Escape) => GameOverResult::MainMenu,
+        _ => GameOverResult::NoSelection,
+    }
+}
@@ -0,0 +1,264 @@
+use crate::unique_file;
+use std::fs;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const HIGH_SCORE_FILE: &str = "highscores.txt";
+const MAX_HIGH_SCORES: usize = 10;
+const FIELD_COUNT: usize = 8;
+
+/// One finished run, stored as a tab separated line in the score file.
+#[derive(Clone)]
+pub struct ScoreEntry {
+    pub score: i32,
+    pub depth: i32,
+    pub kills: i32,
+    pub xp: i32,
+    pub turns: i32,
+    pub name: String,
+    pub date: String,
+    pub cause: String,
+}
+
+impl ScoreEntry {
+    pub fn new(name: &str, cause: &str, depth: i32, kills: i32, xp: i32, turns: i32) -> ScoreEntry {
+        ScoreEntry {
+            score: depth * 100 + kills * 10 + xp,
+            depth,
+            kills,
+            xp,
+            turns,
+            name: clean_field(name),
+            date: today(),
+            cause: clean_field(cause),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.score,
+            self.depth,
+            self.kills,
+            self.xp,
+            self.turns,
+            self.name,
+            self.date,
+            self.cause
+        )
+    }
+
+    fn from_line(line: &str) -> Option<ScoreEntry> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != FIELD_COUNT {
+            return None;
+        }
+        Some(ScoreEntry {
+            score: fields[0].parse().ok()?,
+            depth: fields[1].parse().ok()?,
+            kills: fields[2].parse().ok()?,
+            xp: fields[3].parse().ok()?,
+            turns: fields[4].parse().ok()?,
+            name: fields[5].to_string(),
+            date: fields[6].to_string(),
+            cause: fields[7].to_string(),
+        })
+    }
+}
+
+/// The best runs so far, highest score first.
+pub struct HighScores {
+    pub entries: Vec<ScoreEntry>,
+}
+
+impl Default for HighScores {
+    fn default() -> Self {
+        HighScores::new()
+    }
+}
+
+impl HighScores {
+    pub fn new() -> HighScores {
+        HighScores {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Loads the score file, starting with an empty table when it does not
+    /// exist. A file that cannot be read or parsed is moved aside to a backup
+    /// so the next save does not overwrite it. Returns the table together
+    /// with anything worth telling the player.
+    pub fn load(path: &str) -> (HighScores, Vec<String>) {
+        let mut high_scores = HighScores::new();
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return (high_scores, Vec::new())
+            }
+            Err(error) => {
+                let problem = format!("The high-score file could not be read ({})", error);
+                return (HighScores::new(), vec![back_up(path, &problem)]);
+            }
+        };
+
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            match ScoreEntry::from_line(line) {
+                Some(entry) => high_scores.entries.push(entry),
+                None => {
+                    let problem = "The high-score file was corrupted";
+                    return (HighScores::new(), vec![back_up(path, problem)]);
+                }
+            }
+        }
+        high_scores.sort();
+
+        (high_scores, Vec::new())
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut contents = String::new();
+        for entry in self.entries.iter() {
+            contents.push_str(&entry.to_line());
+            contents.push('\n');
+        }
+        fs::write(path, contents)
+    }
+
+    /// Adds a run to the table and returns its place, if it made the cut.
+    pub fn add(&mut self, entry: ScoreEntry) -> Option<usize> {
+        let place = self
+            .entries
+            .iter()
+            .position(|existing| existing.score < entry.score)
+            .unwrap_or(self.entries.len());
+        if place >= MAX_HIGH_SCORES {
+            return None;
+        }
+        self.entries.insert(place, entry);
+        self.entries.truncate(MAX_HIGH_SCORES);
+        Some(place)
+    }
+
+    fn sort(&mut self) {
+        self.entries
+            .sort_by_key(|entry| std::cmp::Reverse(entry.score));
+        self.entries.truncate(MAX_HIGH_SCORES);
+    }
+}
+
+/// Moves a bad score file to a fresh backup name, so no earlier backup is
+/// overwritten, and says what happened to it.
+fn back_up(path: &str, problem: &str) -> String {
+    let moved = unique_file::create(path, "bak").and_then(|(file, backup)| {
+        drop(file);
+        match fs::rename(path, &backup) {
+            Ok(_) => Ok(backup),
+            Err(error) => {
+                let _ = fs::remove_file(&backup);
+                Err(error)
+            }
+        }
+    });
+    match moved {
+        Ok(backup) => format!("{}, it was moved to {}", problem, backup),
+        Err(error) => format!("{} and could not be backed up: {}", problem, error),
+    }
+}
+
+/// Tabs and newlines would break the line format.
+fn clean_field(text: &str) -> String {
+    text.replace(['\t', '\n', '\r'], " ")
+}
+
+/// Today's date as YYYY-MM-DD in UTC.
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() / 86_400)
+        .unwrap_or(0) as i64;
+
+    // Convert days since 1970-01-01 to a civil date
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    /// A directory of its own for each test, so backups can be counted.
+    fn empty_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("roguelike-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).expect("Unable to create directory");
+        directory
+    }
+
+    fn backups(directory: &PathBuf) -> Vec<PathBuf> {
+        fs::read_dir(directory)
+            .expect("Unable to list directory")
+            .map(|entry| entry.expect("Unable to read entry").path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "bak"))
+            .collect()
+    }
+
+    /// Loads `contents` as a score file and checks it was moved aside whole.
+    fn assert_backed_up(name: &str, contents: &[u8]) {
+        let directory = empty_directory(name);
+        let path = directory.join("highscores.txt");
+        let path = path.to_str().expect("Temporary path is not UTF-8");
+        fs::write(path, contents).expect("Unable to write score file");
+
+        let (high_scores, messages) = HighScores::load(path);
+        assert!(high_scores.entries.is_empty());
+        assert!(!Path::new(path).exists(), "The bad file was left in place");
+        let backups = backups(&directory);
+        assert_eq!(backups.len(), 1);
+        assert_eq!(
+            fs::read(&backups[0]).expect("Unable to read backup"),
+            contents
+        );
+        assert_eq!(messages.len(), 1);
+        assert!(
+            messages[0].contains(backups[0].to_str().unwrap()),
+            "{:?}",
+            messages
+        );
+
+        fs::remove_dir_all(&directory).expect("Unable to remove directory");
+    }
+
+    #[test]
+    fn garbage_is_backed_up() {
+        assert_backed_up("garbage-scores", b"this is not\ta score\n");
+    }
+
+    #[test]
+    fn unreadable_text_is_backed_up() {
+        assert_backed_up("binary-scores", &[0xff, 0xfe, 0x00, 0x42]);
+    }
+
+    #[test]
+    fn a_missing_file_is_an_empty_table() {
+        let directory = empty_directory("missing-scores");
+        let path = directory.join("highscores.txt");
+        let (high_scores, messages) = HighScores::load(path.to_str().unwrap());
+        assert!(high_scores.entries.is_empty());
+        assert!(messages.is_empty());
+        assert!(backups(&directory).is_empty());
+        fs::remove_dir_all(&directory).expect("Unable to remove directory");
+    }
+}
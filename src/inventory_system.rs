@@ -2,8 +2,12 @@ use super::{
     CombatStats, GameLog, InBackpack, Name, Position, Potion, WantsToDrinkPotion, WantsToDropItem,
     WantsToPickupItem,
 };
+use crate::particle_system::ParticleBuilder;
+use rltk::RGB;
 use specs::prelude::*;
 
+const HEAL_FLASH_MS: f32 = 300.0;
+
 pub struct ItemCollectionSystem {}
 
 impl<'a> System<'a> for ItemCollectionSystem {
@@ -55,6 +59,8 @@ impl<'a> System<'a> for PotionUseSystem {
         ReadStorage<'a, Name>,
         ReadStorage<'a, Potion>,
         WriteStorage<'a, CombatStats>,
+        WriteExpect<'a, ParticleBuilder>,
+        ReadStorage<'a, Position>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
@@ -66,6 +72,8 @@ impl<'a> System<'a> for PotionUseSystem {
             names,
             potions,
             mut combat_stats,
+            mut particle_builder,
+            positions,
         ) = data;
 
         for (entity, drink, stats) in (&entities, &wants_drink, &mut combat_stats).join() {
@@ -74,6 +82,16 @@ impl<'a> System<'a> for PotionUseSystem {
                 None => {}
                 Some(potion) => {
                     stats.hp = i32::min(stats.max_hp, stats.hp + potion.heal_amount);
+                    if let Some(pos) = positions.get(entity) {
+                        particle_builder.request(
+                            pos.x,
+                            pos.y,
+                            RGB::named(rltk::GREEN),
+                            RGB::named(rltk::BLACK),
+                            rltk::to_cp437('♥'),
+                            HEAL_FLASH_MS,
+                        );
+                    }
                     if entity == *player_entity {
                         gamelog.entries.push(format!(
                             "You drink the {}, healing {} hp.",
@@ -0,0 +1,361 @@
+use rltk::VirtualKeyCode;
+use std::fs;
+
+pub const KEYBINDINGS_FILE: &str = "keybindings.cfg";
+
+/// Every command the player can trigger from the keyboard.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    MoveUpRight,
+    MoveUpLeft,
+    MoveDownRight,
+    MoveDownLeft,
+    PickUp,
+    Inventory,
+    DropItem,
+    Wait,
+    Explore,
+    Rest,
+    Disarm,
+    Descend,
+    Console,
+    ExportMap,
+    Help,
+}
+
+impl Action {
+    pub const ALL: [Action; 19] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveUpRight,
+        Action::MoveUpLeft,
+        Action::MoveDownRight,
+        Action::MoveDownLeft,
+        Action::PickUp,
+        Action::Inventory,
+        Action::DropItem,
+        Action::Wait,
+        Action::Explore,
+        Action::Rest,
+        Action::Disarm,
+        Action::Descend,
+        Action::Console,
+        Action::ExportMap,
+        Action::Help,
+    ];
+
+    /// Name used for the action in the bindings file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::MoveUp => "move_up",
+            Action::MoveDown => "move_down",
+            Action::MoveUpRight => "move_up_right",
+            Action::MoveUpLeft => "move_up_left",
+            Action::MoveDownRight => "move_down_right",
+            Action::MoveDownLeft => "move_down_left",
+            Action::PickUp => "pick_up",
+            Action::Inventory => "inventory",
+            Action::DropItem => "drop_item",
+            Action::Wait => "wait",
+            Action::Explore => "explore",
+            Action::Rest => "rest",
+            Action::Disarm => "disarm",
+            Action::Descend => "descend",
+            Action::Console => "console",
+            Action::ExportMap => "export_map",
+            Action::Help => "help",
+        }
+    }
+
+    /// Human readable description shown on the help screen.
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "Move west",
+            Action::MoveRight => "Move east",
+            Action::MoveUp => "Move north",
+            Action::MoveDown => "Move south",
+            Action::MoveUpRight => "Move north-east",
+            Action::MoveUpLeft => "Move north-west",
+            Action::MoveDownRight => "Move south-east",
+            Action::MoveDownLeft => "Move south-west",
+            Action::PickUp => "Pick up an item",
+            Action::Inventory => "Use an item",
+            Action::DropItem => "Drop an item",
+            Action::Wait => "Wait a turn",
+            Action::Explore => "Auto-explore",
+            Action::Rest => "Rest until healed",
+            Action::Disarm => "Disarm a trap",
+            Action::Descend => "Go down the stairs",
+            Action::Console => "Wizard console",
+            Action::ExportMap => "Save the level for a bug report",
+            Action::Help => "Show this help",
+        }
+    }
+
+    /// Movement delta for the movement actions.
+    pub fn direction(self) -> Option<(i32, i32)> {
+        match self {
+            Action::MoveLeft => Some((-1, 0)),
+            Action::MoveRight => Some((1, 0)),
+            Action::MoveUp => Some((0, -1)),
+            Action::MoveDown => Some((0, 1)),
+            Action::MoveUpRight => Some((1, -1)),
+            Action::MoveUpLeft => Some((-1, -1)),
+            Action::MoveDownRight => Some((1, 1)),
+            Action::MoveDownLeft => Some((-1, 1)),
+            _ => None,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Action> {
+        Action::ALL
+            .iter()
+            .copied()
+            .find(|action| action.name() == name)
+    }
+
+    fn default_keys(self) -> Vec<VirtualKeyCode> {
+        use VirtualKeyCode::*;
+        match self {
+            Action::MoveLeft => vec![Left, Numpad4, H, A],
+            Action::MoveRight => vec![Right, Numpad6, L, D],
+            Action::MoveUp => vec![Up, Numpad8, K, W],
+            Action::MoveDown => vec![Down, Numpad2, J, S],
+            Action::MoveUpRight => vec![Numpad9, Y],
+            Action::MoveUpLeft => vec![Numpad7, U],
+            Action::MoveDownRight => vec![Numpad3, N],
+            Action::MoveDownLeft => vec![Numpad1, B],
+            Action::PickUp => vec![G],
+            Action::Inventory => vec![I],
+            Action::DropItem => vec![X],
+            Action::Wait => vec![Period, Numpad5],
+            Action::Explore => vec![O],
+            Action::Rest => vec![R],
+            Action::Disarm => vec![T],
+            Action::Descend => vec![Return],
+            Action::Console => vec![Grave],
+            Action::ExportMap => vec![F12],
+            Action::Help => vec![Slash, F1],
+        }
+    }
+}
+
+/// Keys that may be used in the bindings file.
+const BINDABLE_KEYS: [VirtualKeyCode; 78] = {
+    use VirtualKeyCode::*;
+    [
+        Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, A, B, C, D, E, F, G, H, I, J,
+        K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9,
+        F10, F11, F12, Home, End, PageDown, PageUp, Left, Up, Right, Down, Return, Space, Tab,
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+        Comma, Period, Slash, Semicolon, Minus, Equals, Backslash, Grave,
+    ]
+};
+
+pub fn key_name(key: VirtualKeyCode) -> String {
+    format!("{:?}", key)
+}
+
+fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    BINDABLE_KEYS
+        .iter()
+        .copied()
+        .find(|key| key_name(*key).eq_ignore_ascii_case(name))
+}
+
+/// Maps keys to actions. Built from the defaults, then overridden action by
+/// action with whatever the bindings file provides.
+pub struct KeyBindings {
+    pub bindings: Vec<(Action, Vec<VirtualKeyCode>)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings::new()
+    }
+}
+
+impl KeyBindings {
+    pub fn new() -> KeyBindings {
+        KeyBindings {
+            bindings: Action::ALL
+                .iter()
+                .map(|action| (*action, action.default_keys()))
+                .collect(),
+        }
+    }
+
+    /// Loads the bindings file, falling back to the defaults when it does not
+    /// exist. Returns the bindings together with every problem found in it.
+    pub fn load(path: &str) -> (KeyBindings, Vec<String>) {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        KeyBindings::from_config(&contents)
+    }
+
+    /// The defaults overridden by the bindings file `contents`, together with
+    /// every problem found in it.
+    fn from_config(contents: &str) -> (KeyBindings, Vec<String>) {
+        let mut key_bindings = KeyBindings::new();
+        let mut problems: Vec<String> = Vec::new();
+        key_bindings.apply_config(contents, &mut problems);
+        key_bindings.report_conflicts(&mut problems);
+        (key_bindings, problems)
+    }
+
+    /// Parses lines such as `move_left = Left, Numpad4, H` and replaces the
+    /// keys of each listed action. Blank lines and `#` comments are ignored.
+    fn apply_config(&mut self, contents: &str, problems: &mut Vec<String>) {
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (action_name, key_names) = match line.find('=') {
+                Some(split) => (line[..split].trim(), &line[split + 1..]),
+                None => {
+                    problems.push(format!("Bindings line {}: missing '='", line_number + 1));
+                    continue;
+                }
+            };
+            let action = match Action::from_name(action_name) {
+                Some(action) => action,
+                None => {
+                    problems.push(format!(
+                        "Bindings line {}: unknown action '{}'",
+                        line_number + 1,
+                        action_name
+                    ));
+                    continue;
+                }
+            };
+
+            let mut keys: Vec<VirtualKeyCode> = Vec::new();
+            for key_name in key_names
+                .split(',')
+                .map(str::trim)
+                .filter(|k| !k.is_empty())
+            {
+                match key_from_name(key_name) {
+                    Some(key) => keys.push(key),
+                    None => problems.push(format!(
+                        "Bindings line {}: unknown key '{}'",
+                        line_number + 1,
+                        key_name
+                    )),
+                }
+            }
+
+            for binding in self.bindings.iter_mut() {
+                if binding.0 == action {
+                    binding.1 = keys.clone();
+                }
+            }
+        }
+    }
+
+    fn report_conflicts(&self, problems: &mut Vec<String>) {
+        for (i, (action, keys)) in self.bindings.iter().enumerate() {
+            for key in keys.iter() {
+                for (other_action, other_keys) in self.bindings.iter().skip(i + 1) {
+                    if other_keys.contains(key) {
+                        problems.push(format!(
+                            "Key {} is bound to both {} and {}, using {}",
+                            key_name(*key),
+                            action.name(),
+                            other_action.name(),
+                            action.name()
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn action_for(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_action, keys)| keys.contains(&key))
+            .map(|(action, _keys)| *action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use VirtualKeyCode::*;
+
+    fn keys_for(key_bindings: &KeyBindings, action: Action) -> Vec<VirtualKeyCode> {
+        key_bindings
+            .bindings
+            .iter()
+            .find(|(bound, _keys)| *bound == action)
+            .map(|(_action, keys)| keys.clone())
+            .expect("Every action has a binding")
+    }
+
+    #[test]
+    fn missing_file_gives_the_defaults() {
+        let (key_bindings, problems) = KeyBindings::load("no-such-keybindings.cfg");
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(keys_for(&key_bindings, Action::PickUp), vec![G]);
+        assert_eq!(key_bindings.action_for(Numpad4), Some(Action::MoveLeft));
+    }
+
+    #[test]
+    fn listed_actions_get_the_listed_keys() {
+        let config = "
+# Comments and blank lines are skipped
+
+pick_up = Comma, g
+wait=Space
+";
+        let (key_bindings, problems) = KeyBindings::from_config(config);
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(keys_for(&key_bindings, Action::PickUp), vec![Comma, G]);
+        assert_eq!(keys_for(&key_bindings, Action::Wait), vec![Space]);
+        assert_eq!(key_bindings.action_for(Period), None);
+        // Actions left out keep their defaults
+        assert_eq!(keys_for(&key_bindings, Action::Inventory), vec![I]);
+    }
+
+    #[test]
+    fn mistakes_are_reported_by_line() {
+        let config = "pick_up = G
+fly = F
+wait = Period, Teleport
+rest R
+";
+        let (key_bindings, problems) = KeyBindings::from_config(config);
+        assert_eq!(
+            problems,
+            vec![
+                "Bindings line 2: unknown action 'fly'".to_string(),
+                "Bindings line 3: unknown key 'Teleport'".to_string(),
+                "Bindings line 4: missing '='".to_string(),
+            ]
+        );
+        // The keys that could be read are still used, and a broken line
+        // changes nothing
+        assert_eq!(keys_for(&key_bindings, Action::Wait), vec![Period]);
+        assert_eq!(keys_for(&key_bindings, Action::Rest), vec![R]);
+    }
+
+    #[test]
+    fn conflicts_are_reported_and_the_first_action_wins() {
+        let (key_bindings, problems) = KeyBindings::from_config("inventory = G\n");
+        assert_eq!(
+            problems,
+            vec!["Key G is bound to both pick_up and inventory, using pick_up".to_string()]
+        );
+        assert_eq!(key_bindings.action_for(G), Some(Action::PickUp));
+    }
+}
@@ -0,0 +1,48 @@
+use super::{LightSource, Map, Position};
+use rltk::{field_of_view, DistanceAlg, Point, RGB};
+use specs::prelude::*;
+
+/// What every tile gets, with no light source anywhere near it.
+const AMBIENT_LIGHT: f32 = 0.25;
+/// The town is out under the open sky.
+const DAYLIGHT: f32 = 1.0;
+const TOWN_DEPTH: i32 = 0;
+
+pub struct LightingSystem {}
+
+impl<'a> System<'a> for LightingSystem {
+    type SystemData = (
+        WriteExpect<'a, Map>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, LightSource>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut map, positions, lights) = data;
+
+        let ambient = if map.depth == TOWN_DEPTH {
+            DAYLIGHT
+        } else {
+            AMBIENT_LIGHT
+        };
+        for light in map.light.iter_mut() {
+            *light = RGB::from_f32(ambient, ambient, ambient);
+        }
+
+        for (pos, light) in (&positions, &lights).join() {
+            let center = Point::new(pos.x, pos.y);
+            let lit_tiles = field_of_view(center, light.range, &*map);
+            for tile in lit_tiles {
+                if tile.x < 0 || tile.x >= map.width || tile.y < 0 || tile.y >= map.height {
+                    continue;
+                }
+                let distance = DistanceAlg::Pythagoras.distance2d(center, tile);
+                let intensity = (1.0 - distance / light.range as f32).max(0.0);
+                let index = map.get_index_xy(tile.x, tile.y);
+                let total = map.light[index] + light.color * intensity;
+                map.light[index] =
+                    RGB::from_f32(total.r.min(1.0), total.g.min(1.0), total.b.min(1.0));
+            }
+        }
+    }
+}
@@ -1,32 +1,69 @@
 use rltk::{GameState, Point, Rltk};
 use specs::prelude::*;
 
+#[cfg(all(feature = "opengl", feature = "terminal"))]
+compile_error!("Pick one backend: build the terminal feature with --no-default-features");
+
+mod auto_move;
+mod bsp_builder;
+mod cave_builder;
 mod components;
 mod constants;
 mod damage_system;
+mod entity_cleanup;
 mod gamelog;
 mod gui;
+mod highscores;
 mod inventory_system;
+mod keybindings;
+mod lighting_system;
 mod map;
+mod map_check;
+mod map_file;
+mod map_generation;
 mod map_indexing_system;
 mod melee_combat_system;
 mod monster_ai_system;
+mod morgue;
+mod npc_system;
+mod particle_system;
 mod player;
+mod prefabs;
 mod rect;
+mod replay;
+mod run_record;
 mod spawner;
+mod terrain;
+mod terrain_system;
+mod trigger_system;
+mod unique_file;
 mod visibility_system;
+mod wfc_builder;
+mod wizard;
 
+use auto_move::{AutoMove, StartKey};
 pub use components::*;
 use damage_system::DamageSystem;
 use gamelog::GameLog;
+use highscores::HighScores;
 use inventory_system::{ItemCollectionSystem, ItemDropSystem, PotionUseSystem};
-pub use map::{draw_map, Map, TileType};
+use keybindings::KeyBindings;
+use lighting_system::LightingSystem;
+pub use map::{draw_map, Decoration, Map, TileType};
+use map_generation::MapGenerationView;
 use map_indexing_system::MapIndexingSystem;
 use melee_combat_system::MeleeCombatSystem;
 use monster_ai_system::MonsterAI;
+use npc_system::{BuySystem, TalkSystem};
+use particle_system::{ParticleBuilder, ParticleSpawnSystem};
 use player::player_input;
 pub use rect::Rect;
+use replay::Replay;
+use run_record::RunRecord;
+use terrain_system::TerrainSystem;
+use trigger_system::TriggerSystem;
 use visibility_system::VisibilitySystem;
+use wizard::Wizard;
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum RunState {
@@ -36,6 +73,18 @@ pub enum RunState {
     MonsterTurn,
     ShowInventory,
     ShowDropItem,
+    ShowShop {
+        shopkeeper: Entity,
+    },
+    ShowHelp,
+    GameOver,
+    MainMenu {
+        menu_selection: gui::MainMenuSelection,
+    },
+    ShowHighScores,
+    WizardConsole,
+    MapGeneration,
+    NextLevel,
 }
 
 pub struct State {
@@ -43,19 +92,194 @@ pub struct State {
 }
 
 impl State {
+    /// A world with every component registered, the wizard console and the
+    /// map generation view turned off, and no run started yet.
+    fn new() -> State {
+        let mut gs = State { ecs: World::new() };
+        register_components(&mut gs);
+        gs.ecs.insert(Wizard::new(false));
+        gs.ecs.insert(MapGenerationView::new(false));
+        gs
+    }
+
+    /// Throws away the current run, if any, and builds a fresh one from
+    /// `seed`. The new run is recorded for replay.
+    fn new_game(&mut self, seed: u64) {
+        self.ecs.delete_all();
+
+        // Every run sets out from the town
+        let mut rng = rltk::RandomNumberGenerator::seeded(seed);
+        let map: Map = Map::new_level(0, &mut rng);
+        let (player_x, player_y) = map.rooms[0].center();
+
+        let player_entity = spawner::player(&mut self.ecs, player_x, player_y);
+
+        self.ecs.insert(rng);
+        spawner::populate_level(&mut self.ecs, &map, player_x, player_y);
+
+        self.ecs.insert(map);
+        self.ecs.insert(Point::new(player_x, player_y));
+        self.ecs.insert(player_entity);
+        self.ecs.insert(AutoMove::Idle);
+        self.ecs.insert(StartKey(None));
+        self.ecs.insert(ParticleBuilder::new());
+        self.ecs.insert(RunRecord::new());
+        self.ecs.insert(Replay::record(seed));
+        self.ecs.write_resource::<Wizard>().god_mode = false;
+        self.ecs.insert(GameLog {
+            entries: vec!["Welcome to Rusty Roguelike".to_string()],
+        });
+    }
+
+    /// Builds the next level down. The player keeps what they carry, and
+    /// everything else is left behind.
+    fn goto_next_level(&mut self) {
+        let depth = self.ecs.fetch::<Map>().depth + 1;
+        let map = {
+            let mut rng = self.ecs.write_resource::<rltk::RandomNumberGenerator>();
+            Map::new_level(depth, &mut rng)
+        };
+        self.enter_level(map);
+        self.ecs
+            .write_resource::<GameLog>()
+            .entries
+            .push(format!("You descend to depth {}.", depth));
+    }
+
+    /// Moves the player, and what they carry, onto `map` and fills it with
+    /// monsters and items.
+    fn enter_level(&mut self, map: Map) {
+        let left_behind: Vec<Entity> = {
+            let player_entity = *self.ecs.fetch::<Entity>();
+            let entities = self.ecs.entities();
+            let backpack = self.ecs.read_storage::<InBackpack>();
+            entities
+                .join()
+                .filter(|entity| {
+                    *entity != player_entity
+                        && backpack.get(*entity).map(|pack| pack.owner) != Some(player_entity)
+                })
+                .collect()
+        };
+        for entity in left_behind {
+            self.ecs
+                .delete_entity(entity)
+                .expect("Unable to delete entity");
+        }
+
+        let (player_x, player_y) = map.rooms[0].center();
+        spawner::populate_level(&mut self.ecs, &map, player_x, player_y);
+        self.ecs.insert(map);
+
+        let player_entity = *self.ecs.fetch::<Entity>();
+        if let Some(pos) = self.ecs.write_storage::<Position>().get_mut(player_entity) {
+            pos.x = player_x;
+            pos.y = player_y;
+        }
+        if let Some(viewshed) = self.ecs.write_storage::<Viewshed>().get_mut(player_entity) {
+            viewshed.dirty = true;
+        }
+        self.ecs.insert(Point::new(player_x, player_y));
+        self.ecs.insert(AutoMove::Idle);
+    }
+
+    /// Plays the steps of a turn that need neither input nor the screen.
+    /// Any other state is waiting on the player and comes back unchanged.
+    fn advance(&mut self, run_state: RunState) -> RunState {
+        match run_state {
+            RunState::PreRun => {
+                self.run_systems();
+                self.ecs.maintain();
+                RunState::AwaitingInput
+            }
+            RunState::PlayerTurn => {
+                particle_system::clear_particles(&mut self.ecs);
+                self.ecs.write_resource::<RunRecord>().turns += 1;
+                self.run_systems();
+                self.ecs.maintain();
+                RunState::MonsterTurn
+            }
+            RunState::MonsterTurn => {
+                self.run_systems();
+                self.ecs.maintain();
+                if terrain_system::player_slowed(&mut self.ecs) {
+                    RunState::MonsterTurn
+                } else {
+                    RunState::AwaitingInput
+                }
+            }
+            RunState::NextLevel => {
+                self.goto_next_level();
+                map_generation::start(&mut self.ecs, RunState::PreRun)
+            }
+            other => other,
+        }
+    }
+
+    /// Turns the answer to the inventory, drop or shop menu into the
+    /// player's intent. The menu stays open while there is no answer yet.
+    fn item_menu_result(
+        &mut self,
+        menu: RunState,
+        result: (gui::ItemMenuResult, Option<Entity>),
+    ) -> RunState {
+        let item = match result {
+            (gui::ItemMenuResult::Cancel, _) => return RunState::AwaitingInput,
+            (gui::ItemMenuResult::Selected, Some(item)) => item,
+            _ => return menu,
+        };
+        let player_entity = *self.ecs.fetch::<Entity>();
+        match menu {
+            RunState::ShowInventory => {
+                self.ecs
+                    .write_storage::<WantsToDrinkPotion>()
+                    .insert(player_entity, WantsToDrinkPotion { potion: item })
+                    .expect("Unable to insert intent");
+            }
+            RunState::ShowShop { .. } => {
+                self.ecs
+                    .write_storage::<WantsToBuy>()
+                    .insert(player_entity, WantsToBuy { item })
+                    .expect("Unable to insert intent");
+            }
+            _ => {
+                self.ecs
+                    .write_storage::<WantsToDropItem>()
+                    .insert(player_entity, WantsToDropItem { item })
+                    .expect("Unable to insert intent");
+            }
+        }
+        RunState::PlayerTurn
+    }
+
     fn run_systems(&mut self) {
         let mut visibility = VisibilitySystem {};
         visibility.run_now(&self.ecs);
 
+        let mut lighting = LightingSystem {};
+        lighting.run_now(&self.ecs);
+
         let mut mob = MonsterAI {};
         mob.run_now(&self.ecs);
 
         let mut mapindex = MapIndexingSystem {};
         mapindex.run_now(&self.ecs);
 
+        let mut terrain = TerrainSystem {};
+        terrain.run_now(&self.ecs);
+
+        let mut triggers = TriggerSystem {};
+        triggers.run_now(&self.ecs);
+
         let mut melee_combat = MeleeCombatSystem {};
         melee_combat.run_now(&self.ecs);
 
+        let mut talk = TalkSystem {};
+        talk.run_now(&self.ecs);
+
+        let mut buy = BuySystem {};
+        buy.run_now(&self.ecs);
+
         let mut damage = DamageSystem {};
         damage.run_now(&self.ecs);
 
@@ -68,13 +292,20 @@ impl State {
         let mut drop_item = ItemDropSystem {};
         drop_item.run_now(&self.ecs);
 
+        let mut particles = ParticleSpawnSystem {};
+        particles.run_now(&self.ecs);
+
         self.ecs.maintain();
+        entity_cleanup::remove_dangling_references(&mut self.ecs);
+        #[cfg(debug_assertions)]
+        entity_cleanup::check_entity_references(&self.ecs);
     }
 }
 
 impl GameState for State {
     fn tick(&mut self, ctx: &mut Rltk) {
         ctx.cls();
+        particle_system::cull_dead_particles(&mut self.ecs, ctx);
 
         let mut new_run_state;
         {
@@ -83,62 +314,75 @@ impl GameState for State {
         }
 
         match new_run_state {
-            RunState::PreRun => {
-                self.run_systems();
-                self.ecs.maintain();
-                new_run_state = RunState::AwaitingInput;
-            }
+            RunState::PreRun
+            | RunState::PlayerTurn
+            | RunState::MonsterTurn
+            | RunState::NextLevel => new_run_state = self.advance(new_run_state),
             RunState::AwaitingInput => {
-                new_run_state = player_input(self, ctx);
+                let key_pressed = auto_move::is_interrupting_key(&self.ecs, ctx.key);
+                let interrupted = replay::interrupted(&self.ecs, key_pressed);
+                new_run_state = match auto_move::auto_move_step(&mut self.ecs, interrupted) {
+                    Some(run_state) => run_state,
+                    None => {
+                        let run_state = player_input(self, ctx);
+                        self.ecs.insert(StartKey(ctx.key));
+                        run_state
+                    }
+                };
             }
-            RunState::PlayerTurn => {
-                self.run_systems();
-                self.ecs.maintain();
-                new_run_state = RunState::MonsterTurn;
+            RunState::ShowInventory => {
+                let player_entity = *self.ecs.fetch::<Entity>();
+                let result = replay::menu_choice(self, ctx, player_entity, gui::show_inventory);
+                new_run_state = self.item_menu_result(new_run_state, result);
             }
-            RunState::MonsterTurn => {
-                self.run_systems();
-                self.ecs.maintain();
-                new_run_state = RunState::AwaitingInput;
+            RunState::ShowDropItem => {
+                let player_entity = *self.ecs.fetch::<Entity>();
+                let result = replay::menu_choice(self, ctx, player_entity, gui::drop_item_menu);
+                new_run_state = self.item_menu_result(new_run_state, result);
             }
-            RunState::ShowInventory => {
-                let result = gui::show_inventory(self, ctx);
-                match result.0 {
-                    gui::ItemMenuResult::Cancel => new_run_state = RunState::AwaitingInput,
-                    gui::ItemMenuResult::NoResponse => {}
-                    gui::ItemMenuResult::Selected => {
-                        let item_entity = result.1.unwrap();
-                        let mut intent = self.ecs.write_storage::<WantsToDrinkPotion>();
-                        intent
-                            .insert(
-                                *self.ecs.fetch::<Entity>(),
-                                WantsToDrinkPotion {
-                                    potion: item_entity,
-                                },
-                            )
-                            .expect("Unable to insert intent");
-
-                        new_run_state = RunState::PlayerTurn;
-                    }
+            RunState::ShowShop { shopkeeper } => {
+                let result = replay::menu_choice(self, ctx, shopkeeper, |gs, ctx| {
+                    gui::shop_menu(gs, ctx, shopkeeper)
+                });
+                new_run_state = self.item_menu_result(new_run_state, result);
+            }
+            RunState::ShowHelp => {
+                if gui::show_help(self, ctx) == gui::ItemMenuResult::Cancel {
+                    new_run_state = RunState::AwaitingInput;
                 }
             }
-            RunState::ShowDropItem => {
-                let result = gui::drop_item_menu(self, ctx);
-                match result.0 {
-                    gui::ItemMenuResult::Cancel => new_run_state = RunState::AwaitingInput,
-                    gui::ItemMenuResult::NoResponse => {}
-                    gui::ItemMenuResult::Selected => {
-                        let item_entity = result.1.unwrap();
-                        let mut intent = self.ecs.write_storage::<WantsToDropItem>();
-                        intent
-                            .insert(
-                                *self.ecs.fetch::<Entity>(),
-                                WantsToDropItem { item: item_entity },
-                            )
-                            .expect("Unable to insert intent");
-                        new_run_state = RunState::PlayerTurn;
+            RunState::GameOver => {
+                if gui::game_over(self, ctx) == gui::GameOverResult::MainMenu {
+                    self.new_game(random_seed());
+                    new_run_state = RunState::MainMenu {
+                        menu_selection: gui::MainMenuSelection::NewGame,
+                    };
+                }
+            }
+            RunState::MainMenu { .. } => match gui::main_menu(self, ctx) {
+                gui::MainMenuResult::NoSelection { selected } => {
+                    new_run_state = RunState::MainMenu {
+                        menu_selection: selected,
                     }
                 }
+                gui::MainMenuResult::Selected { selected } => match selected {
+                    gui::MainMenuSelection::NewGame => {
+                        new_run_state = map_generation::start(&mut self.ecs, RunState::PreRun)
+                    }
+                    gui::MainMenuSelection::HighScores => new_run_state = RunState::ShowHighScores,
+                    gui::MainMenuSelection::Quit => ctx.quit(),
+                },
+            },
+            RunState::WizardConsole => new_run_state = wizard::console_input(self, ctx),
+            RunState::MapGeneration => {
+                new_run_state = map_generation::show_step(&mut self.ecs, ctx)
+            }
+            RunState::ShowHighScores => {
+                if gui::show_high_scores(self, ctx) == gui::ItemMenuResult::Cancel {
+                    new_run_state = RunState::MainMenu {
+                        menu_selection: gui::MainMenuSelection::HighScores,
+                    };
+                }
             }
         }
 
@@ -147,22 +391,47 @@ impl GameState for State {
             *run_writer = new_run_state;
         }
 
+        // Menus, the game-over screen and map generation have the console
+        // to themselves
+        match new_run_state {
+            RunState::GameOver
+            | RunState::MainMenu { .. }
+            | RunState::ShowHighScores
+            | RunState::MapGeneration => return,
+            _ => {}
+        }
+
         damage_system::delete_the_dead(&mut self.ecs);
         draw_map(&self.ecs, ctx);
 
         let positions = self.ecs.read_storage::<Position>();
         let renderables = self.ecs.read_storage::<Renderable>();
+        let hidden = self.ecs.read_storage::<Hidden>();
+        let monsters = self.ecs.read_storage::<Monster>();
         let map = self.ecs.fetch::<Map>();
+        let player_pos = *self.ecs.fetch::<Point>();
 
-        let mut data = (&positions, &renderables).join().collect::<Vec<_>>();
-        data.sort_by(|&a, &b| b.1.render_order.cmp(&a.1.render_order));
-        for (pos, render) in data.iter() {
+        let mut data = (&positions, &renderables, !&hidden, monsters.maybe())
+            .join()
+            .collect::<Vec<_>>();
+        data.sort_by_key(|&a| std::cmp::Reverse(a.1.render_order));
+        for (pos, render, _hidden, monster) in data.iter() {
             let index = map.get_index_xy(pos.x, pos.y);
-            if map.visible_tiles[index] {
-                ctx.set(pos.x, pos.y, render.fg, render.bg, render.glyph)
+            // Monsters keep to the shadows
+            let seen = if monster.is_some() {
+                map.can_spot(index, player_pos)
+            } else {
+                map.visible_tiles[index]
+            };
+            if seen {
+                let light = map.light[index];
+                ctx.set(pos.x, pos.y, render.fg * light, render.bg, render.glyph)
             }
         }
-        gui::draw_ui(&self.ecs, ctx)
+        gui::draw_ui(&self.ecs, ctx);
+        if new_run_state == RunState::WizardConsole {
+            gui::draw_wizard_console(&self.ecs, ctx);
+        }
     }
 }
 
@@ -170,51 +439,127 @@ fn register_components(gs: &mut State) {
     gs.ecs.register::<Position>();
     gs.ecs.register::<Renderable>();
     gs.ecs.register::<LeftMover>();
+    gs.ecs.register::<LightSource>();
     gs.ecs.register::<Player>();
     gs.ecs.register::<Viewshed>();
     gs.ecs.register::<Monster>();
     gs.ecs.register::<Name>();
+    gs.ecs.register::<Npc>();
+    gs.ecs.register::<Shopkeeper>();
+    gs.ecs.register::<Healer>();
     gs.ecs.register::<BlocksTile>();
     gs.ecs.register::<CombatStats>();
     gs.ecs.register::<WantsToMelee>();
+    gs.ecs.register::<WantsToTalk>();
+    gs.ecs.register::<WantsToBuy>();
     gs.ecs.register::<Item>();
+    gs.ecs.register::<Corpse>();
+    gs.ecs.register::<Purse>();
+    gs.ecs.register::<Price>();
+    gs.ecs.register::<LootTable>();
     gs.ecs.register::<Potion>();
+    gs.ecs.register::<Key>();
     gs.ecs.register::<InBackpack>();
     gs.ecs.register::<WantsToPickupItem>();
     gs.ecs.register::<WantsToDrinkPotion>();
     gs.ecs.register::<WantsToDropItem>();
     gs.ecs.register::<SufferDamage>();
+    gs.ecs.register::<LastHitByPlayer>();
+    gs.ecs.register::<Trap>();
+    gs.ecs.register::<Hidden>();
+    gs.ecs.register::<EntityMoved>();
+    gs.ecs.register::<Wading>();
+    gs.ecs.register::<Swimmer>();
+    gs.ecs.register::<Alerted>();
+    gs.ecs.register::<ParticleLifetime>();
+}
+
+fn random_seed() -> u64 {
+    rltk::RandomNumberGenerator::new().next_u64()
 }
 
 fn main() -> rltk::BError {
     use rltk::RltkBuilder;
 
+    // `--check-maps COUNT` only validates generated levels, without a window
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--check-maps") {
+        let count = match args.get(i + 1) {
+            Some(count) => count.parse().map_err(|_| "--check-maps needs a number")?,
+            None => 1000,
+        };
+        map_check::check_maps(count)?;
+        return Ok(());
+    }
+    // `--check-replay FILE` plays a recorded game through, also without one
+    if let Some(i) = args.iter().position(|arg| arg == "--check-replay") {
+        let path = args.get(i + 1).ok_or("--check-replay needs a file")?;
+        let checksums = replay::check(path)?;
+        println!("Replay finished, {} checksums matched", checksums);
+        return Ok(());
+    }
+
+    #[cfg_attr(not(feature = "opengl"), allow(unused_mut))]
     let mut context = RltkBuilder::simple80x50()
         .with_title("Roguelike Game")
         .build()?;
+    #[cfg(feature = "opengl")]
     context.with_post_scanlines(true);
-    let mut gs = State { ecs: World::new() };
+    let mut gs = State::new();
 
-    register_components(&mut gs);
-
-    // Set a new map
-    let map: Map = Map::new_rooms_and_corridors();
-    let (player_x, player_y) = map.rooms[0].center();
-
-    let player_entity = spawner::player(&mut gs.ecs, player_x, player_y);
-
-    gs.ecs.insert(rltk::RandomNumberGenerator::new());
-    for room in map.rooms.iter().skip(1) {
-        spawner::spawn_room(&mut gs.ecs, room);
+    // `--replay FILE` plays a recorded game back. `--wizard` turns on the
+    // wizard console, and `--show-mapgen` shows each level being built.
+    // `--map FILE` starts on a level saved by the map export.
+    let mut replay_file = None;
+    let mut map_file = None;
+    let mut wizard_mode = cfg!(feature = "wizard");
+    let mut show_mapgen = false;
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        match arg.as_str() {
+            "--replay" => replay_file = args.get(i + 1),
+            "--wizard" => wizard_mode = true,
+            "--show-mapgen" => show_mapgen = true,
+            "--map" => map_file = args.get(i + 1),
+            _ => {}
+        }
+    }
+    gs.ecs.insert(Wizard::new(wizard_mode));
+    gs.ecs.insert(MapGenerationView::new(show_mapgen));
+    match replay_file {
+        Some(path) => {
+            let (seed, replay) = Replay::load(path)?;
+            gs.new_game(seed);
+            gs.ecs.insert(replay);
+            let run_state = map_generation::start(&mut gs.ecs, RunState::PreRun);
+            gs.ecs.insert(run_state);
+        }
+        None => {
+            gs.new_game(random_seed());
+            gs.ecs.insert(RunState::MainMenu {
+                menu_selection: gui::MainMenuSelection::NewGame,
+            });
+        }
+    }
+    if let Some(path) = map_file {
+        gs.enter_level(map_file::load_level(path, 1)?);
+        // The seed no longer builds this level
+        replay::abandon(&gs.ecs, "the level was loaded from a file");
+        let run_state = map_generation::start(&mut gs.ecs, RunState::PreRun);
+        gs.ecs.insert(run_state);
+    }
+    let (key_bindings, binding_problems) = KeyBindings::load(keybindings::KEYBINDINGS_FILE);
+    let (high_scores, score_problems) = HighScores::load(highscores::HIGH_SCORE_FILE);
+    gs.ecs.insert(key_bindings);
+    gs.ecs.insert(high_scores);
+    {
+        let mut log = gs.ecs.write_resource::<GameLog>();
+        log.entries.extend(binding_problems);
+        log.entries.extend(score_problems);
+        if wizard_mode {
+            log.entries
+                .push("Wizard mode: press ` to open the console".to_string());
+        }
     }
-
-    gs.ecs.insert(map);
-    gs.ecs.insert(Point::new(player_x, player_y));
-    gs.ecs.insert(player_entity);
-    gs.ecs.insert(RunState::PreRun);
-    gs.ecs.insert(GameLog {
-        entries: vec!["Welcome to Rusty Roguelike".to_string()],
-    });
 
     rltk::main_loop(context, gs)
 }
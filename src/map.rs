@@ -1,28 +1,128 @@
-use super::Rect;
-use rltk::{Algorithm2D, BaseMap, DistanceAlg, Point, RandomNumberGenerator, Rltk, SmallVec, RGB};
+use super::{BlocksTile, Rect};
+use crate::bsp_builder;
+use crate::cave_builder;
+use crate::prefabs;
+use crate::terrain;
+use crate::wfc_builder;
+use rltk::{
+    Algorithm2D, BaseMap, DijkstraMap, DistanceAlg, FontCharType, Point, RandomNumberGenerator,
+    Rltk, SmallVec, RGB,
+};
 use specs::prelude::*;
 use std::cmp::{max, min};
 
 use crate::constants::{
     COORDINATE_X, MAP_HEIGHT, MAP_TOTAL_DIMENSION, MAP_WIDTH, MAX_ROOMS, MAX_SIZE_ROOM,
-    MIN_SIZE_ROOM,
+    MIN_SIZE_ROOM, VISIBLE_TILES_RANGE,
 };
 
-#[derive(PartialEq, Copy, Clone)]
+const DOOR_PATH_COST: f32 = 2.0;
+/// Wading takes about twice as long as walking.
+const SHALLOW_WATER_PATH_COST: f32 = 2.0;
+/// Swimming is slower still, for the few that can.
+const DEEP_WATER_PATH_COST: f32 = 3.0;
+/// Climbing over rubble is a little slower than wading.
+const RUBBLE_PATH_COST: f32 = 2.5;
+/// Worth a long way round, but not worth giving up a chase for.
+const LAVA_PATH_COST: f32 = 4.0;
+/// Levels with fewer reachable tiles than this are thrown away and built again.
+pub const MIN_REACHABLE_TILES: usize = 150;
+/// Monsters on tiles with at least this much light can be made out anywhere
+/// in view. In less light they have to be closer, down to right next to the
+/// player in the dark.
+const LIT_LEVEL: f32 = 0.45;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub enum TileType {
     Wall,
     Floor,
+    DoorClosed,
+    DoorOpen,
+    DoorLocked,
+    DownStairs,
+    ShallowWater,
+    DeepWater,
+    Lava,
+    Rubble,
+}
+
+impl TileType {
+    /// Whether something can stand on (or walk through) this tile. Closed
+    /// doors count, since bumping into them opens them. Deep water does not:
+    /// only a `Swimmer` gets across it, see `Map::is_open_water`.
+    pub fn is_walkable(self) -> bool {
+        !matches!(
+            self,
+            TileType::Wall | TileType::DoorLocked | TileType::DeepWater
+        )
+    }
+
+    pub fn is_opaque(self) -> bool {
+        matches!(
+            self,
+            TileType::Wall | TileType::DoorClosed | TileType::DoorLocked | TileType::Rubble
+        )
+    }
+
+    /// Ground that takes an extra turn to get across.
+    pub fn is_slow(self) -> bool {
+        matches!(self, TileType::ShallowWater | TileType::Rubble)
+    }
+
+    /// Ground that hurts whatever stands on it.
+    pub fn is_hazard(self) -> bool {
+        self == TileType::Lava
+    }
+
+    /// How many times longer than floor this tile takes to path across.
+    fn path_cost(self) -> f32 {
+        match self {
+            TileType::ShallowWater => SHALLOW_WATER_PATH_COST,
+            TileType::DeepWater => DEEP_WATER_PATH_COST,
+            TileType::Rubble => RUBBLE_PATH_COST,
+            TileType::Lava => LAVA_PATH_COST,
+            TileType::Wall
+            | TileType::Floor
+            | TileType::DoorClosed
+            | TileType::DoorOpen
+            | TileType::DoorLocked
+            | TileType::DownStairs => 1.0,
+        }
+    }
+}
+
+/// Marks left on the floor that stay on the map for the rest of the level.
+/// Later variants win when several end up on the same tile.
+#[derive(PartialEq, PartialOrd, Copy, Clone, Debug)]
+pub enum Decoration {
+    Bloodstain,
+    ScorchMark,
+    /// Remembered where a body was left, so it still shows once out of view.
+    Corpse,
 }
 
 pub struct Map {
     pub tiles: Vec<TileType>,
     pub rooms: Vec<Rect>,
+    /// False when `rooms` only marks the player start with a one-tile room,
+    /// on levels that are not made of rooms.
+    pub has_rooms: bool,
     pub width: i32,
     pub height: i32,
+    pub depth: i32,
     pub revealed_tiles: Vec<bool>,
+    pub decorations: Vec<Option<Decoration>>,
     pub visible_tiles: Vec<bool>,
+    /// The light on each tile, from none at all to full color.
+    pub light: Vec<RGB>,
     pub blocked: Vec<bool>,
     pub tile_content: Vec<Vec<Entity>>,
+    /// Things placed by prefabs, by name, for the spawner to create.
+    pub spawns: Vec<(usize, String)>,
+    /// Groups of tiles filled like rooms are, for levels without rooms.
+    pub spawn_regions: Vec<Vec<usize>>,
+    /// The tiles after each generation step, oldest first.
+    pub history: Vec<Vec<TileType>>,
 }
 
 impl Map {
@@ -30,7 +130,19 @@ impl Map {
         (y as usize * self.width as usize) + x as usize
     }
 
-    fn apply_room_to_map(&mut self, room: &Rect) {
+    /// Keeps the player start as a one-tile room, where the rest of the game
+    /// looks for it, on a level with no rooms.
+    pub fn mark_start(&mut self, x: i32, y: i32) {
+        self.rooms = vec![Rect::new(x, y, 0, 0)];
+        self.has_rooms = false;
+    }
+
+    /// Remembers the tiles as they are now, for watching the level being built.
+    pub fn take_snapshot(&mut self) {
+        self.history.push(self.tiles.clone());
+    }
+
+    pub fn apply_room_to_map(&mut self, room: &Rect) {
         for y in room.y1 + 1..=room.y2 {
             for x in room.x1 + 1..=room.x2 {
                 let index = self.get_index_xy(x, y);
@@ -44,7 +156,7 @@ impl Map {
             let index = self.get_index_xy(x, y);
 
             if index > 0 && index < MAP_TOTAL_DIMENSION {
-                self.tiles[index as usize] = TileType::Floor;
+                self.tiles[index] = TileType::Floor;
             }
         }
     }
@@ -53,7 +165,7 @@ impl Map {
         for y in min(y1, y2)..=max(y1, y2) {
             let index = self.get_index_xy(x, y);
             if index > 0 && index < MAP_TOTAL_DIMENSION {
-                self.tiles[index as usize] = TileType::Floor;
+                self.tiles[index] = TileType::Floor;
             }
         }
     }
@@ -68,23 +180,220 @@ impl Map {
 
     fn join_rooms(&mut self, rng: &mut RandomNumberGenerator, new_room: &Rect) {
         if !self.rooms.is_empty() {
-            let (new_x, new_y) = new_room.center();
-            let (prev_x, prev_y) = self.rooms[self.rooms.len() - 1].center();
+            let previous = self.rooms[self.rooms.len() - 1].center();
+            self.tunnel_between(previous, new_room.center(), rng);
+        }
+    }
 
-            if rng.range(0, 2) == 1 {
-                self.apply_horizontal_tunnel(prev_x, new_x, prev_y);
-                self.apply_vertical_tunnel(prev_y, new_y, new_x);
-            } else {
-                self.apply_vertical_tunnel(prev_y, new_y, prev_x);
-                self.apply_horizontal_tunnel(prev_x, new_x, new_y);
+    /// Digs an L-shaped corridor between two points.
+    pub fn tunnel_between(
+        &mut self,
+        from: (i32, i32),
+        to: (i32, i32),
+        rng: &mut RandomNumberGenerator,
+    ) {
+        if rng.range(0, 2) == 1 {
+            self.apply_horizontal_tunnel(from.0, to.0, from.1);
+            self.apply_vertical_tunnel(from.1, to.1, to.0);
+        } else {
+            self.apply_vertical_tunnel(from.1, to.1, from.0);
+            self.apply_horizontal_tunnel(from.0, to.0, to.1);
+        }
+    }
+
+    /// Which tiles can be walked to from `start`. Locked doors count as open,
+    /// since their key is always left on the near side.
+    pub fn reachable_from(&mut self, start: usize) -> Vec<bool> {
+        for (index, tile) in self.tiles.iter().enumerate() {
+            self.blocked[index] = matches!(tile, TileType::Wall | TileType::DeepWater);
+        }
+        let max_depth = (self.width * self.height) as f32 * DOOR_PATH_COST;
+        let dijkstra_map = DijkstraMap::new(self.width, self.height, &[start], self, max_depth);
+        self.populate_blocked();
+
+        dijkstra_map
+            .map
+            .iter()
+            .map(|distance| *distance < f32::MAX)
+            .collect()
+    }
+
+    /// Walls off everything the player cannot reach from the start, and
+    /// forgets rooms and prefab spawns that were cut off entirely. Deep
+    /// water that can be seen from the shore stays. Returns how many tiles
+    /// are left to walk on, which is none for a level without a start.
+    pub fn cull_unreachable(&mut self) -> usize {
+        if self.rooms.is_empty() {
+            return 0;
+        }
+        let (start_x, start_y) = self.rooms[0].center();
+        let start = self.get_index_xy(start_x, start_y);
+        let reachable = self.reachable_from(start);
+        let kept = self.with_shore_water(&reachable);
+
+        for (tile, kept) in self.tiles.iter_mut().zip(kept.iter()) {
+            if !kept {
+                *tile = TileType::Wall;
+            }
+        }
+        let width = self.width;
+        let start_room = self.rooms.remove(0);
+        self.rooms.retain(|room| {
+            (room.y1 + 1..=room.y2)
+                .any(|y| (room.x1 + 1..=room.x2).any(|x| reachable[(y * width + x) as usize]))
+        });
+        self.rooms.insert(0, start_room);
+        self.spawns.retain(|(index, _name)| reachable[*index]);
+        for region in self.spawn_regions.iter_mut() {
+            region.retain(|index| reachable[*index]);
+        }
+        self.spawn_regions.retain(|region| !region.is_empty());
+        self.populate_blocked();
+
+        reachable.iter().filter(|reachable| **reachable).count()
+    }
+
+    /// `reachable`, plus the deep water that touches it, directly or
+    /// through more deep water.
+    fn with_shore_water(&self, reachable: &[bool]) -> Vec<bool> {
+        let mut kept = reachable.to_vec();
+        let mut open: Vec<usize> = (0..kept.len()).filter(|index| kept[*index]).collect();
+        while let Some(index) = open.pop() {
+            let (x, y) = (index as i32 % self.width, index as i32 / self.width);
+            for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))) {
+                let (next_x, next_y) = (x + dx, y + dy);
+                if next_x < 0 || next_x >= self.width || next_y < 0 || next_y >= self.height {
+                    continue;
+                }
+                let next = self.get_index_xy(next_x, next_y);
+                if !kept[next] && self.tiles[next] == TileType::DeepWater {
+                    kept[next] = true;
+                    open.push(next);
+                }
+            }
+        }
+        kept
+    }
+
+    fn exit_cost(&self, index: usize, base_cost: f32) -> f32 {
+        let cost = base_cost * self.tiles[index].path_cost();
+        if self.tiles[index] == TileType::DoorClosed {
+            cost + DOOR_PATH_COST
+        } else {
+            cost
+        }
+    }
+
+    /// Puts a closed door on every single-tile gap where a corridor crosses
+    /// the wall around a room.
+    fn place_doors(&mut self) {
+        let mut doors: Vec<usize> = Vec::new();
+        for room in self.rooms.iter() {
+            let mut candidates: Vec<(i32, i32, bool)> = Vec::new();
+            for x in room.x1 + 1..=room.x2 {
+                candidates.push((x, room.y1, true));
+                candidates.push((x, room.y2 + 1, true));
             }
+            for y in room.y1 + 1..=room.y2 {
+                candidates.push((room.x1, y, false));
+                candidates.push((room.x2 + 1, y, false));
+            }
+
+            for (x, y, horizontal_wall) in candidates {
+                if x < 1 || x > self.width - 2 || y < 1 || y > self.height - 2 {
+                    continue;
+                }
+                let index = self.get_index_xy(x, y);
+                if self.tiles[index] != TileType::Floor {
+                    continue;
+                }
+
+                // The gap must be framed by wall on both sides
+                let (side_a, side_b) = if horizontal_wall {
+                    (self.get_index_xy(x - 1, y), self.get_index_xy(x + 1, y))
+                } else {
+                    (self.get_index_xy(x, y - 1), self.get_index_xy(x, y + 1))
+                };
+                if self.tiles[side_a] == TileType::Wall && self.tiles[side_b] == TileType::Wall {
+                    doors.push(index);
+                }
+            }
+        }
+
+        for index in doors {
+            self.tiles[index] = TileType::DoorClosed;
+        }
+    }
+
+    /// Locks one of the doors that do not belong to the starting room.
+    fn lock_a_door(&mut self, rng: &mut RandomNumberGenerator) {
+        let start_room = &self.rooms[0];
+        let doors: Vec<usize> = self
+            .tiles
+            .iter()
+            .enumerate()
+            .filter(|(index, tile)| {
+                let x = *index as i32 % self.width;
+                let y = *index as i32 / self.width;
+                let next_to_start = x >= start_room.x1
+                    && x <= start_room.x2 + 1
+                    && y >= start_room.y1
+                    && y <= start_room.y2 + 1;
+                **tile == TileType::DoorClosed && !next_to_start
+            })
+            .map(|(index, _tile)| index)
+            .collect();
+
+        if let Some(door) = rng.random_slice_entry(&doors) {
+            self.tiles[*door] = TileType::DoorLocked;
         }
     }
 
     pub fn populate_blocked(&mut self) {
         for (index, tile) in self.tiles.iter_mut().enumerate() {
-            self.blocked[index] = *tile == TileType::Wall;
+            self.blocked[index] = !tile.is_walkable();
+        }
+    }
+
+    pub fn add_decoration(&mut self, index: usize, decoration: Decoration) {
+        if !self.tiles[index].is_walkable() {
+            return;
+        }
+        match self.decorations[index] {
+            Some(current) if current > decoration => {}
+            _ => self.decorations[index] = Some(decoration),
+        }
+    }
+
+    /// Forgets the body on `index` once it is seen to be gone, leaving the
+    /// blood it lay in.
+    pub fn forget_corpse(&mut self, index: usize) {
+        if self.decorations[index] == Some(Decoration::Corpse) {
+            self.decorations[index] = Some(Decoration::Bloodstain);
+        }
+    }
+
+    /// Deep water with nothing blocking it. It is always blocked, so a
+    /// swimmer has to check for it on top of `blocked`.
+    pub fn is_open_water(&self, index: usize, blockers: &ReadStorage<BlocksTile>) -> bool {
+        self.tiles[index] == TileType::DeepWater
+            && !self.tile_content[index]
+                .iter()
+                .any(|entity| blockers.contains(*entity))
+    }
+
+    /// Whether the player, standing at `viewer`, could make out a monster on
+    /// `index`. It has to be in view, and the darker its tile the closer it
+    /// has to be.
+    pub fn can_spot(&self, index: usize, viewer: Point) -> bool {
+        if !self.visible_tiles[index] {
+            return false;
         }
+        let light = self.light[index];
+        let brightness = light.r.max(light.g).max(light.b);
+        let spot_range = 1.5 + brightness / LIT_LEVEL * VISIBLE_TILES_RANGE as f32;
+        let target = Point::new(index as i32 % self.width, index as i32 / self.width);
+        DistanceAlg::Pythagoras.distance2d(viewer, target) <= spot_range
     }
 
     pub fn clear_content_index(&mut self) {
@@ -93,19 +402,67 @@ impl Map {
         }
     }
 
-    pub fn new_rooms_and_corridors() -> Map {
-        let mut map = Map {
+    /// Generates levels for `depth` until one leaves enough room to play,
+    /// and a way down, after the parts that cannot be reached are culled.
+    /// Depths with a level prefab always get it, as drawn; other levels get
+    /// water, rubble and lava scattered over them.
+    pub fn new_level(depth: i32, rng: &mut RandomNumberGenerator) -> Map {
+        loop {
+            let mut map = if let Some(prefab) = prefabs::level_prefab(depth) {
+                let mut map = Map::new(depth);
+                prefabs::build_level(&mut map, &prefab.rows(), &[]);
+                map
+            } else {
+                // Rooms and corridors are the most common
+                let mut map = match rng.roll_dice(1, 6) {
+                    1 => cave_builder::build(depth, rng),
+                    2 => bsp_builder::build(depth, rng),
+                    3 => bsp_builder::build_interior(depth, rng),
+                    4 => wfc_builder::build(depth, rng),
+                    _ => Map::new_rooms_and_corridors(depth, rng),
+                };
+                terrain::scatter(&mut map, rng);
+                map
+            };
+            if map.cull_unreachable() >= MIN_REACHABLE_TILES
+                && map.tiles.contains(&TileType::DownStairs)
+            {
+                map.take_snapshot();
+                return map;
+            }
+        }
+    }
+
+    /// Puts the stairs down at `index`.
+    pub fn place_stairs(&mut self, index: usize) {
+        self.tiles[index] = TileType::DownStairs;
+    }
+
+    /// A level that is solid wall, for builders to carve into.
+    pub fn new(depth: i32) -> Map {
+        Map {
             tiles: vec![TileType::Wall; MAP_TOTAL_DIMENSION],
             rooms: Vec::new(),
+            has_rooms: true,
             width: MAP_WIDTH as i32,
             height: MAP_HEIGHT as i32,
+            depth,
             revealed_tiles: vec![false; MAP_TOTAL_DIMENSION],
+            decorations: vec![None; MAP_TOTAL_DIMENSION],
             visible_tiles: vec![false; MAP_TOTAL_DIMENSION],
+            light: vec![RGB::new(); MAP_TOTAL_DIMENSION],
             blocked: vec![false; MAP_TOTAL_DIMENSION],
             tile_content: vec![Vec::new(); MAP_TOTAL_DIMENSION],
-        };
+            spawns: Vec::new(),
+            spawn_regions: Vec::new(),
+            history: Vec::new(),
+        }
+    }
 
-        let mut rng = RandomNumberGenerator::new();
+    /// Rooms placed at random and joined by corridors, with the stairs in
+    /// the last room.
+    pub fn new_rooms_and_corridors(depth: i32, rng: &mut RandomNumberGenerator) -> Map {
+        let mut map = Map::new(depth);
 
         for _ in 0..MAX_ROOMS {
             let width = rng.range(MIN_SIZE_ROOM, MAX_SIZE_ROOM);
@@ -123,18 +480,40 @@ impl Map {
 
             if ok {
                 map.apply_room_to_map(&new_room);
-                map.join_rooms(&mut rng, &new_room);
+                map.take_snapshot();
+                if !map.rooms.is_empty() {
+                    map.join_rooms(rng, &new_room);
+                    map.take_snapshot();
+                }
                 map.rooms.push(new_room);
             }
         }
 
+        prefabs::place_vault(&mut map, rng);
+        prefabs::place_sectional(&mut map, rng);
+        map.finish_rooms(rng);
+
         map
     }
+
+    /// Adds doors, one of them locked, and puts the stairs down in the last
+    /// room. Shared by the builders that make rooms.
+    pub fn finish_rooms(&mut self, rng: &mut RandomNumberGenerator) {
+        self.place_doors();
+        self.lock_a_door(rng);
+        if self.rooms.len() > 1 {
+            let (stairs_x, stairs_y) = self.rooms[self.rooms.len() - 1].center();
+            let stairs = self.get_index_xy(stairs_x, stairs_y);
+            self.place_stairs(stairs);
+        }
+        self.take_snapshot();
+        self.populate_blocked();
+    }
 }
 
 impl BaseMap for Map {
     fn is_opaque(&self, index: usize) -> bool {
-        self.tiles[index] == TileType::Wall
+        self.tiles[index].is_opaque()
     }
 
     fn get_available_exits(&self, index: usize) -> SmallVec<[(usize, f32); 10]> {
@@ -145,30 +524,30 @@ impl BaseMap for Map {
 
         // Cardinal directions
         if self.is_exit_valid(x - 1, y) {
-            exits.push((index - 1, 1.0))
+            exits.push((index - 1, self.exit_cost(index - 1, 1.0)))
         };
         if self.is_exit_valid(x + 1, y) {
-            exits.push((index + 1, 1.0))
+            exits.push((index + 1, self.exit_cost(index + 1, 1.0)))
         };
         if self.is_exit_valid(x, y - 1) {
-            exits.push((index - w, 1.0))
+            exits.push((index - w, self.exit_cost(index - w, 1.0)))
         };
         if self.is_exit_valid(x, y + 1) {
-            exits.push((index + w, 1.0))
+            exits.push((index + w, self.exit_cost(index + w, 1.0)))
         };
 
         // Diagonal
         if self.is_exit_valid(x - 1, y - 1) {
-            exits.push(((index - w) - 1, 1.45));
+            exits.push(((index - w) - 1, self.exit_cost((index - w) - 1, 1.45)));
         }
         if self.is_exit_valid(x + 1, y - 1) {
-            exits.push(((index - w) + 1, 1.45));
+            exits.push(((index - w) + 1, self.exit_cost((index - w) + 1, 1.45)));
         }
         if self.is_exit_valid(x - 1, y + 1) {
-            exits.push(((index + w) - 1, 1.45));
+            exits.push(((index + w) - 1, self.exit_cost((index + w) - 1, 1.45)));
         }
         if self.is_exit_valid(x + 1, y + 1) {
-            exits.push(((index + w) + 1, 1.45));
+            exits.push(((index + w) + 1, self.exit_cost((index + w) + 1, 1.45)));
         }
 
         exits
@@ -196,24 +575,31 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
     for (index, tile) in map.tiles.iter().enumerate() {
         // Render a tile depending upon the tile type
         if map.revealed_tiles[index] {
-            let glyph;
-            let mut fg;
+            let (mut glyph, mut fg) = tile_glyph(*tile);
+            let mut bg = RGB::from_f32(0., 0., 0.);
 
-            match tile {
-                TileType::Floor => {
-                    glyph = rltk::to_cp437('.');
-                    fg = RGB::from_f32(0.0, 0.5, 0.5);
-                }
-                TileType::Wall => {
-                    glyph = rltk::to_cp437('#');
-                    fg = RGB::from_f32(0., 1.0, 0.);
+            match map.decorations[index] {
+                None => {}
+                Some(Decoration::Bloodstain) => bg = RGB::from_f32(0.4, 0., 0.),
+                Some(Decoration::ScorchMark) => bg = RGB::from_f32(0.15, 0.15, 0.15),
+                Some(Decoration::Corpse) => {
+                    bg = RGB::from_f32(0.4, 0., 0.);
+                    // In view, the corpse item itself is drawn on top
+                    if !map.visible_tiles[index] {
+                        glyph = rltk::to_cp437('%');
+                        fg = RGB::from_f32(0.6, 0., 0.);
+                    }
                 }
             }
 
-            if !map.visible_tiles[index] {
-                fg = fg.to_greyscale()
+            if map.visible_tiles[index] {
+                fg = fg * map.light[index];
+                bg = bg * map.light[index];
+            } else {
+                fg = fg.to_greyscale();
+                bg = bg.to_greyscale();
             }
-            ctx.set(x, y, fg, RGB::from_f32(0., 0., 0.), glyph);
+            ctx.set(x, y, fg, bg, glyph);
         }
 
         // Move the coordinates
@@ -224,3 +610,28 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
         }
     }
 }
+
+pub fn tile_glyph(tile: TileType) -> (FontCharType, RGB) {
+    match tile {
+        TileType::Floor => (rltk::to_cp437('.'), RGB::from_f32(0.0, 0.5, 0.5)),
+        TileType::Wall => (rltk::to_cp437('#'), RGB::from_f32(0., 1.0, 0.)),
+        TileType::DoorClosed => (rltk::to_cp437('+'), RGB::named(rltk::CHOCOLATE)),
+        TileType::DoorOpen => (rltk::to_cp437('/'), RGB::named(rltk::CHOCOLATE)),
+        TileType::DoorLocked => (rltk::to_cp437('+'), RGB::named(rltk::GOLD)),
+        TileType::DownStairs => (rltk::to_cp437('>'), RGB::named(rltk::CYAN)),
+        TileType::ShallowWater => (rltk::to_cp437('~'), RGB::named(rltk::STEEL_BLUE)),
+        TileType::DeepWater => (rltk::to_cp437('≈'), RGB::named(rltk::NAVY_BLUE)),
+        TileType::Lava => (rltk::to_cp437('░'), RGB::named(rltk::ORANGE_RED)),
+        TileType::Rubble => (rltk::to_cp437(':'), RGB::named(rltk::BURLYWOOD)),
+    }
+}
+
+/// Draws one step of `Map.history` with every tile revealed.
+pub fn draw_snapshot(map: &Map, tiles: &[TileType], ctx: &mut Rltk) {
+    for (index, tile) in tiles.iter().enumerate() {
+        let (glyph, fg) = tile_glyph(*tile);
+        let x = index as i32 % map.width;
+        let y = index as i32 / map.width;
+        ctx.set(x, y, fg, RGB::from_f32(0., 0., 0.), glyph);
+    }
+}
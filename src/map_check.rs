@@ -0,0 +1,135 @@
+use super::map::MIN_REACHABLE_TILES;
+use super::{Map, TileType};
+use rltk::RandomNumberGenerator;
+
+/// Depths to generate for every seed; the town and the deepest one are
+/// prefab levels.
+const CHECKED_DEPTHS: [i32; 4] = [0, 1, 2, 3];
+/// Stop listing problems after this many.
+const MAX_REPORTED: usize = 10;
+
+/// Generates levels from seeds `0..count` on every checked depth and makes
+/// sure each one is playable: the same seed gives the same level, the player
+/// starts on open ground, every walkable tile can be reached, and prefab
+/// spawns are on walkable tiles. Run with `--check-maps COUNT`.
+pub fn check_maps(count: u64) -> Result<(), String> {
+    let mut problems: Vec<String> = Vec::new();
+    let mut levels = 0;
+
+    for seed in 0..count {
+        for depth in CHECKED_DEPTHS.iter() {
+            let mut map = Map::new_level(*depth, &mut RandomNumberGenerator::seeded(seed));
+            let again = Map::new_level(*depth, &mut RandomNumberGenerator::seeded(seed));
+            levels += 1;
+
+            for problem in check_map(&mut map, &again) {
+                problems.push(format!("Seed {} depth {}: {}", seed, depth, problem));
+            }
+        }
+    }
+
+    println!("Checked {} levels", levels);
+    if problems.is_empty() {
+        return Ok(());
+    }
+    for problem in problems.iter().take(MAX_REPORTED) {
+        println!("{}", problem);
+    }
+    Err(format!(
+        "{} problems found in generated maps",
+        problems.len()
+    ))
+}
+
+fn check_map(map: &mut Map, again: &Map) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if map.tiles != again.tiles {
+        problems.push("the same seed built a different level".to_string());
+    }
+    problems.extend(playable_problems(map));
+    problems
+}
+
+/// Everything that keeps `map` from being played, whichever builder made
+/// it: rooms off the map, a start inside a wall, tiles that cannot be
+/// reached, too little room, no way down and spawns inside walls.
+pub fn playable_problems(map: &mut Map) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for room in map.rooms.iter() {
+        if room.x1 < 0 || room.y1 < 0 || room.x2 >= map.width || room.y2 >= map.height {
+            problems.push(format!(
+                "a room reaches off the map, {},{} to {},{}",
+                room.x1, room.y1, room.x2, room.y2
+            ));
+        }
+    }
+    if map.rooms.is_empty() {
+        problems.push("there is no start".to_string());
+        return problems;
+    }
+
+    let (start_x, start_y) = map.rooms[0].center();
+    let start = map.get_index_xy(start_x, start_y);
+    if !map.tiles[start].is_walkable() {
+        problems.push(format!(
+            "the player starts inside a wall at {},{}",
+            start_x, start_y
+        ));
+        return problems;
+    }
+
+    let reachable = map.reachable_from(start);
+    let unreachable = map
+        .tiles
+        .iter()
+        .zip(reachable.iter())
+        .filter(|(tile, reachable)| tile.is_walkable() && !**reachable)
+        .count();
+    if unreachable > 0 {
+        problems.push(format!("{} walkable tiles cannot be reached", unreachable));
+    }
+
+    let reachable_count = reachable.iter().filter(|reachable| **reachable).count();
+    if reachable_count < MIN_REACHABLE_TILES {
+        problems.push(format!("only {} tiles can be reached", reachable_count));
+    }
+
+    let stairs = map
+        .tiles
+        .iter()
+        .position(|tile| *tile == TileType::DownStairs);
+    if !stairs.is_some_and(|stairs| reachable[stairs]) {
+        problems.push("there is no way down".to_string());
+    }
+
+    for (index, name) in map.spawns.iter() {
+        if !map.tiles[*index].is_walkable() {
+            problems.push(format!("{} is spawned inside a wall", name));
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_maps_are_playable() {
+        if let Err(error) = check_maps(10) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Too slow for every test run; try `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn many_generated_maps_are_playable() {
+        if let Err(error) = check_maps(1000) {
+            panic!("{}", error);
+        }
+    }
+}
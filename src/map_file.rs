@@ -0,0 +1,283 @@
+use super::map::MIN_REACHABLE_TILES;
+use super::{map, prefabs, unique_file, Map, Name, Player, Position, TileType, Trap};
+use rltk::rex::{XpCell, XpFile, XpLayer};
+use rltk::XpColor;
+use specs::prelude::*;
+use std::fs;
+use std::path::Path;
+
+const MAP_DIRECTORY: &str = "maps";
+/// Files with this extension are REXPaint images, anything else is text.
+const REXPAINT_EXTENSION: &str = "xp";
+
+/// The player and spawns that stand on a tile other than floor, by legend
+/// character, column and row.
+type Overlay = Vec<(i32, i32, char)>;
+
+/// Saves the current level as a bug-report attachment under `maps/` and
+/// returns the path it was written to.
+pub fn export_for_report(ecs: &World) -> Result<String, String> {
+    fs::create_dir_all(MAP_DIRECTORY).map_err(|error| error.to_string())?;
+    let depth = ecs.fetch::<Map>().depth;
+    let prefix = format!("{}/level-depth{}", MAP_DIRECTORY, depth);
+    let (_file, path) = unique_file::create(&prefix, "txt").map_err(|error| error.to_string())?;
+    export_level(ecs, &path, true)?;
+    Ok(path)
+}
+
+/// Writes the current level to `path`, as a REXPaint image when it ends in
+/// `.xp` and as text in the prefab legend otherwise. The player is always
+/// marked as the start; monsters, items and traps only `with_entities`.
+///
+/// Text marks them in place on plain floor. Anything standing on another
+/// tile is listed after the map instead, following a blank line, as its
+/// character, column and row, so the tile underneath is kept.
+pub fn export_level(ecs: &World, path: &str, with_entities: bool) -> Result<(), String> {
+    let map = ecs.fetch::<Map>();
+    let mut entities: Vec<Option<char>> = vec![None; map.tiles.len()];
+
+    let positions = ecs.read_storage::<Position>();
+    let players = ecs.read_storage::<Player>();
+    let traps = ecs.read_storage::<Trap>();
+    let names = ecs.read_storage::<Name>();
+    for (pos, player, trap, name) in (&positions, players.maybe(), traps.maybe(), &names).join() {
+        let glyph = if player.is_some() {
+            Some('@')
+        } else if !with_entities {
+            None
+        } else if trap.is_some() {
+            prefabs::spawn_char("Trap")
+        } else {
+            prefabs::spawn_char(&name.name)
+        };
+        // The player always wins the tile
+        let index = map.get_index_xy(pos.x, pos.y);
+        if glyph.is_some() && entities[index] != Some('@') {
+            entities[index] = glyph;
+        }
+    }
+
+    let result = if is_rexpaint(path) {
+        write_rexpaint(path, &map, &entities)
+    } else {
+        let mut text = String::new();
+        let mut listed = String::new();
+        for (index, tile) in map.tiles.iter().enumerate() {
+            let (x, y) = (index as i32 % map.width, index as i32 / map.width);
+            match entities[index] {
+                Some(glyph) if *tile == TileType::Floor => text.push(glyph),
+                Some(glyph) => {
+                    listed.push_str(&format!("{} {} {}\n", glyph, x, y));
+                    text.push(prefabs::tile_char(*tile));
+                }
+                None => text.push(prefabs::tile_char(*tile)),
+            }
+            if x == map.width - 1 {
+                text.push('\n');
+            }
+        }
+        if !listed.is_empty() {
+            text.push('\n');
+            text.push_str(&listed);
+        }
+        fs::write(path, text)
+    };
+    result.map_err(|error| format!("Unable to write {}: {}", path, error))
+}
+
+/// Tiles go on the first layer and the player, monsters and items on a
+/// second one, so they can be edited separately in REXPaint.
+fn write_rexpaint(path: &str, map: &Map, entities: &[Option<char>]) -> std::io::Result<()> {
+    let (width, height) = (map.width as usize, map.height as usize);
+    let mut xp = XpFile::new(width, height);
+    let mut entity_layer = XpLayer::new(width, height);
+
+    for (index, tile) in map.tiles.iter().enumerate() {
+        let (x, y) = (index % width, index / width);
+        if let Some(cell) = xp.layers[0].get_mut(x, y) {
+            *cell = XpCell {
+                ch: prefabs::tile_char(*tile) as u32,
+                fg: XpColor::from(map::tile_glyph(*tile).1),
+                bg: XpColor::BLACK,
+            };
+        }
+        if let Some(cell) = entity_layer.get_mut(x, y) {
+            *cell = match entities[index] {
+                Some(glyph) => XpCell {
+                    ch: glyph as u32,
+                    fg: XpColor::new(255, 255, 0),
+                    bg: XpColor::BLACK,
+                },
+                None => XpCell {
+                    ch: 0,
+                    fg: XpColor::BLACK,
+                    bg: XpColor::TRANSPARENT,
+                },
+            };
+        }
+    }
+    xp.layers.push(entity_layer);
+
+    let mut file = fs::File::create(path)?;
+    xp.write(&mut file)
+}
+
+/// Builds a level from a file written by `export_level`, or drawn by hand in
+/// the same legend. It must mark the player start with `@`, and be playable
+/// the way a generated level is: enough room and a way down.
+pub fn load_level(path: &str, depth: i32) -> Result<Map, String> {
+    // Whatever cannot be reached from the start has been walled up already
+    let map = load_template(path, depth)?;
+    let reachable = map.tiles.iter().filter(|tile| tile.is_walkable()).count();
+    if reachable < MIN_REACHABLE_TILES {
+        return Err(format!(
+            "{} has only {} tiles to walk on, at least {} are needed",
+            path, reachable, MIN_REACHABLE_TILES
+        ));
+    }
+    if !map.tiles.contains(&TileType::DownStairs) {
+        return Err(format!("{} has no way down (>) from the start", path));
+    }
+    Ok(map)
+}
+
+/// Reads a level the way `load_level` does, but without asking for it to be
+/// playable. Good enough as a pattern for the wave function collapse builder.
+pub fn load_template(path: &str, depth: i32) -> Result<Map, String> {
+    let (rows, overlay) = if is_rexpaint(path) {
+        read_rexpaint(path).map_err(|error| format!("Unable to read {}: {}", path, error))?
+    } else {
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("Unable to read {}: {}", path, error))?;
+        read_text(&text).map_err(|error| format!("{}: {}", path, error))?
+    };
+
+    let start_in_rows = rows.iter().any(|row| row.contains(&'@'));
+    if !start_in_rows && !overlay.iter().any(|(_x, _y, glyph)| *glyph == '@') {
+        return Err(format!("{} has no player start (@)", path));
+    }
+    let mut map = Map::new(depth);
+    prefabs::build_level(&mut map, &rows, &overlay);
+    map.cull_unreachable();
+    map.take_snapshot();
+    Ok(map)
+}
+
+/// The rows of a text map, and the list of what stands on tiles other than
+/// floor that `export_level` writes after it.
+fn read_text(text: &str) -> Result<(Vec<Vec<char>>, Overlay), String> {
+    let mut lines = text.lines().skip_while(|line| line.is_empty());
+    let rows: Vec<Vec<char>> = lines
+        .by_ref()
+        .take_while(|line| !line.is_empty())
+        .map(|line| line.chars().collect())
+        .collect();
+
+    let mut overlay = Vec::new();
+    for line in lines.filter(|line| !line.trim().is_empty()) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let entry = match words.as_slice() {
+            [glyph, x, y] if glyph.chars().count() == 1 => {
+                match (glyph.chars().next(), x.parse(), y.parse()) {
+                    (Some(glyph), Ok(x), Ok(y)) => Some((x, y, glyph)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match entry {
+            Some(entry) => overlay.push(entry),
+            None => return Err(format!("\"{}\" is not a character, column and row", line)),
+        }
+    }
+    Ok((rows, overlay))
+}
+
+/// Flattens the layers of a REXPaint image, later layers drawn over earlier
+/// ones wherever they are not transparent. The player and spawns on later
+/// layers go in the overlay instead, so the tile under them is kept.
+fn read_rexpaint(path: &str) -> std::io::Result<(Vec<Vec<char>>, Overlay)> {
+    let mut file = fs::File::open(path)?;
+    let xp = XpFile::read(&mut file)?;
+    let height = xp
+        .layers
+        .iter()
+        .map(|layer| layer.height)
+        .max()
+        .unwrap_or(0);
+    let width = xp.layers.iter().map(|layer| layer.width).max().unwrap_or(0);
+
+    let mut rows = vec![vec![' '; width]; height];
+    let mut overlay = Vec::new();
+    for (layer_number, layer) in xp.layers.iter().enumerate() {
+        for (y, row) in rows.iter_mut().enumerate() {
+            for (x, glyph) in row.iter_mut().enumerate() {
+                let cell = match layer.get(x, y) {
+                    Some(cell) if cell.ch != 0 && !cell.bg.is_transparent() => cell,
+                    _ => continue,
+                };
+                let ch = std::char::from_u32(cell.ch).unwrap_or(' ');
+                if layer_number > 0 && prefabs::is_entity_char(ch) {
+                    overlay.push((x as i32, y as i32, ch));
+                } else {
+                    *glyph = ch;
+                }
+            }
+        }
+    }
+    Ok((rows, overlay))
+}
+
+fn is_rexpaint(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension == REXPAINT_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+
+    /// Exports the town with the player standing on the stairs down, and
+    /// loads it back.
+    fn round_trip(extension: &str) -> Map {
+        let mut gs = State::new();
+        gs.new_game(1);
+        let stairs = {
+            let map = gs.ecs.fetch::<Map>();
+            let index = map
+                .tiles
+                .iter()
+                .position(|tile| *tile == TileType::DownStairs)
+                .expect("The town has no stairs down");
+            (index as i32 % map.width, index as i32 / map.width)
+        };
+        let player_entity = *gs.ecs.fetch::<Entity>();
+        if let Some(pos) = gs.ecs.write_storage::<Position>().get_mut(player_entity) {
+            pos.x = stairs.0;
+            pos.y = stairs.1;
+        }
+
+        let path = std::env::temp_dir().join(format!("roguelike-round-trip.{}", extension));
+        let path = path.to_str().expect("Temporary path is not UTF-8");
+        export_level(&gs.ecs, path, true).expect("Unable to export");
+        let map = load_level(path, 0);
+        fs::remove_file(path).expect("Unable to remove level");
+        map.expect("The exported level does not load")
+    }
+
+    #[test]
+    fn player_on_the_stairs_keeps_them_in_text() {
+        let map = round_trip("txt");
+        let (x, y) = map.rooms[0].center();
+        assert_eq!(map.tiles[map.get_index_xy(x, y)], TileType::DownStairs);
+    }
+
+    #[test]
+    fn player_on_the_stairs_keeps_them_in_rexpaint() {
+        let map = round_trip(REXPAINT_EXTENSION);
+        let (x, y) = map.rooms[0].center();
+        assert_eq!(map.tiles[map.get_index_xy(x, y)], TileType::DownStairs);
+    }
+}
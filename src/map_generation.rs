@@ -0,0 +1,70 @@
+use super::{map, Map, RunState};
+use rltk::{Rltk, RGB};
+use specs::prelude::*;
+
+/// How long each generation step stays on screen, in milliseconds.
+const STEP_MS: f32 = 150.0;
+
+/// Plays back the steps that built the level, with the whole map revealed,
+/// before the game starts. Only shown when started with `--show-mapgen`.
+pub struct MapGenerationView {
+    pub enabled: bool,
+    step: usize,
+    timer: f32,
+    next_state: RunState,
+}
+
+impl MapGenerationView {
+    pub fn new(enabled: bool) -> MapGenerationView {
+        MapGenerationView {
+            enabled,
+            step: 0,
+            timer: 0.0,
+            next_state: RunState::PreRun,
+        }
+    }
+}
+
+/// Starts watching the current level being built, if the view is turned
+/// on, and goes on to `next_state` afterwards.
+pub fn start(ecs: &mut World, next_state: RunState) -> RunState {
+    let mut view = ecs.write_resource::<MapGenerationView>();
+    if !view.enabled || ecs.fetch::<Map>().history.is_empty() {
+        return next_state;
+    }
+    view.step = 0;
+    view.timer = 0.0;
+    view.next_state = next_state;
+    RunState::MapGeneration
+}
+
+/// Draws the current step and moves on once it has been shown long enough.
+/// Any key skips the rest.
+pub fn show_step(ecs: &mut World, ctx: &mut Rltk) -> RunState {
+    let mut view = ecs.write_resource::<MapGenerationView>();
+    let map = ecs.fetch::<Map>();
+    if ctx.key.is_some() || view.step >= map.history.len() {
+        return view.next_state;
+    }
+
+    map::draw_snapshot(&map, &map.history[view.step], ctx);
+    let progress = format!(
+        "Generating: step {} of {}",
+        view.step + 1,
+        map.history.len()
+    );
+    ctx.print_color(
+        1,
+        map.height - 1,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        &progress,
+    );
+
+    view.timer += ctx.frame_time_ms;
+    if view.timer > STEP_MS {
+        view.timer = 0.0;
+        view.step += 1;
+    }
+    RunState::MapGeneration
+}
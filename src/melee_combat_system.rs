@@ -1,6 +1,11 @@
-use super::{CombatStats, GameLog, Name, SufferDamage, WantsToMelee};
+use super::{CombatStats, GameLog, Name, Position, SufferDamage, WantsToMelee};
+use crate::particle_system::ParticleBuilder;
+use crate::run_record::RunRecord;
+use rltk::RGB;
 use specs::prelude::*;
 
+const HIT_FLASH_MS: f32 = 200.0;
+
 pub struct MeleeCombatSystem {}
 
 impl<'a> System<'a> for MeleeCombatSystem {
@@ -12,18 +17,36 @@ impl<'a> System<'a> for MeleeCombatSystem {
         WriteStorage<'a, SufferDamage>,
         WriteStorage<'a, WantsToMelee>,
         WriteExpect<'a, GameLog>,
+        WriteExpect<'a, ParticleBuilder>,
+        ReadStorage<'a, Position>,
+        ReadExpect<'a, Entity>,
+        WriteExpect<'a, RunRecord>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (entities, names, combat_stats, mut inflict_damage, mut wants_melee, mut log) = data;
+        let (
+            entities,
+            names,
+            combat_stats,
+            mut inflict_damage,
+            mut wants_melee,
+            mut log,
+            mut particle_builder,
+            positions,
+            player_entity,
+            mut record,
+        ) = data;
 
-        for (_entity, name, stats, wants_melee) in
+        for (entity, name, stats, wants_melee) in
             (&entities, &names, &combat_stats, &wants_melee).join()
         {
             if stats.hp > 0 {
                 let target_stats = combat_stats.get(wants_melee.target).unwrap();
 
                 if target_stats.hp > 0 {
+                    if wants_melee.target == *player_entity {
+                        record.last_attacker = Some(name.name.clone());
+                    }
                     let target_name = names.get(wants_melee.target).unwrap();
                     let damage = i32::max(0, stats.power - target_stats.defense);
 
@@ -37,7 +60,22 @@ impl<'a> System<'a> for MeleeCombatSystem {
                             "{} hits {}, for {} hp",
                             &name.name, &target_name.name, damage
                         ));
-                        SufferDamage::new_damage(&mut inflict_damage, wants_melee.target, damage);
+                        SufferDamage::new_damage(
+                            &mut inflict_damage,
+                            wants_melee.target,
+                            damage,
+                            entity == *player_entity,
+                        );
+                        if let Some(pos) = positions.get(wants_melee.target) {
+                            particle_builder.request(
+                                pos.x,
+                                pos.y,
+                                RGB::named(rltk::RED),
+                                RGB::named(rltk::BLACK),
+                                rltk::to_cp437('‼'),
+                                HIT_FLASH_MS,
+                            );
+                        }
                     }
                 }
             }
@@ -1,5 +1,8 @@
-use super::{Map, Monster, Position, RunState, Viewshed, WantsToMelee};
-use rltk::Point;
+use super::{
+    Alerted, BlocksTile, EntityMoved, Map, Monster, Npc, NpcBehavior, Position, RunState, Swimmer,
+    TileType, Viewshed, Wading, WantsToMelee,
+};
+use rltk::{Point, RandomNumberGenerator};
 use specs::prelude::*;
 
 pub struct MonsterAI {}
@@ -11,11 +14,18 @@ impl<'a> System<'a> for MonsterAI {
         ReadExpect<'a, Point>,
         ReadExpect<'a, Entity>,
         ReadExpect<'a, RunState>,
+        WriteExpect<'a, RandomNumberGenerator>,
         Entities<'a>,
         WriteStorage<'a, Viewshed>,
         ReadStorage<'a, Monster>,
+        ReadStorage<'a, Npc>,
         WriteStorage<'a, Position>,
         WriteStorage<'a, WantsToMelee>,
+        WriteStorage<'a, Alerted>,
+        WriteStorage<'a, EntityMoved>,
+        WriteStorage<'a, Wading>,
+        ReadStorage<'a, Swimmer>,
+        ReadStorage<'a, BlocksTile>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
@@ -24,17 +34,37 @@ impl<'a> System<'a> for MonsterAI {
             player_pos,
             player_entity,
             runstate,
+            mut rng,
             entities,
             mut viewshed,
             monster,
+            npcs,
             mut position,
             mut wants_to_melee,
+            mut alerted,
+            mut entity_moved,
+            mut wading,
+            swimmers,
+            blockers,
         ) = data;
 
         if *runstate == RunState::MonsterTurn {
-            for (entity, mut viewshed, _monster, mut pos) in
+            for (entity, viewshed, _monster, pos) in
                 (&entities, &mut viewshed, &monster, &mut position).join()
             {
+                // Climbing out of water or over rubble takes the whole turn
+                if wading.remove(entity).is_some() {
+                    continue;
+                }
+
+                let water: Vec<usize> = if swimmers.contains(entity) {
+                    (0..map.tiles.len())
+                        .filter(|index| map.is_open_water(*index, &blockers))
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+
                 let distance =
                     rltk::DistanceAlg::Pythagoras.distance2d(Point::new(pos.x, pos.y), *player_pos);
 
@@ -49,22 +79,122 @@ impl<'a> System<'a> for MonsterAI {
                         .expect("Unable to insert attack");
                 } else if viewshed.visible_tiles.contains(&*player_pos) {
                     // Path to the player
-                    let path = rltk::a_star_search(
-                        map.get_index_xy(pos.x, pos.y),
-                        map.get_index_xy(player_pos.x, player_pos.y),
-                        &*map,
-                    );
-                    if path.success && path.steps.len() > 1 {
-                        let mut index = map.get_index_xy(pos.x, pos.y);
-                        map.blocked[index] = false;
-                        pos.x = path.steps[1] as i32 % map.width;
-                        pos.y = path.steps[1] as i32 / map.width;
-                        index = map.get_index_xy(pos.x, pos.y);
-                        map.blocked[index] = true;
-                        viewshed.dirty = true;
+                    alerted.remove(entity);
+                    let target = map.get_index_xy(player_pos.x, player_pos.y);
+                    if step_towards(&mut map, pos, viewshed, target, false, &water) == Step::Moved {
+                        entity_moved
+                            .insert(entity, EntityMoved {})
+                            .expect("Unable to insert marker");
                     }
+                } else if let Some(alert) = alerted.get(entity) {
+                    // Investigate the noise until there is nothing left to find
+                    let target = map.get_index_xy(alert.x, alert.y);
+                    match step_towards(&mut map, pos, viewshed, target, true, &water) {
+                        Step::Moved => {
+                            entity_moved
+                                .insert(entity, EntityMoved {})
+                                .expect("Unable to insert marker");
+                        }
+                        Step::OpenedDoor => {}
+                        Step::Stuck => {
+                            alerted.remove(entity);
+                        }
+                    }
+                }
+            }
+
+            // Friendly folk never fight, they just go about their business
+            for (entity, npc, pos) in (&entities, &npcs, &mut position).join() {
+                if npc.behavior == NpcBehavior::Wander && wander(&mut map, pos, &mut rng) {
+                    entity_moved
+                        .insert(entity, EntityMoved {})
+                        .expect("Unable to insert marker");
                 }
             }
         }
     }
 }
+
+#[derive(PartialEq)]
+enum Step {
+    Moved,
+    OpenedDoor,
+    Stuck,
+}
+
+/// Takes one step along the path to `target`, opening a closed door instead
+/// if one is in the way. Unless `avoid_hazards` is false, as when chasing
+/// the player, the path never crosses a hazard. It may cross the deep
+/// `water` tiles given, which a swimmer can enter.
+fn step_towards(
+    map: &mut Map,
+    pos: &mut Position,
+    viewshed: &mut Viewshed,
+    target: usize,
+    avoid_hazards: bool,
+    water: &[usize],
+) -> Step {
+    let hazards: Vec<usize> = if avoid_hazards {
+        (0..map.tiles.len())
+            .filter(|index| map.tiles[*index].is_hazard() && !map.blocked[*index])
+            .collect()
+    } else {
+        Vec::new()
+    };
+    for index in hazards.iter() {
+        map.blocked[*index] = true;
+    }
+    for index in water.iter() {
+        map.blocked[*index] = false;
+    }
+    let path = rltk::a_star_search(map.get_index_xy(pos.x, pos.y), target, &*map);
+    for index in hazards {
+        map.blocked[index] = false;
+    }
+    for index in water.iter() {
+        map.blocked[*index] = true;
+    }
+    if !path.success || path.steps.len() < 2 {
+        return Step::Stuck;
+    }
+
+    if map.tiles[path.steps[1]] == TileType::DoorClosed {
+        // Opening the door takes the whole turn
+        map.tiles[path.steps[1]] = TileType::DoorOpen;
+        viewshed.dirty = true;
+        return Step::OpenedDoor;
+    }
+
+    // Deep water left behind goes back to blocking everything else
+    let mut index = map.get_index_xy(pos.x, pos.y);
+    map.blocked[index] = !map.tiles[index].is_walkable();
+    pos.x = path.steps[1] as i32 % map.width;
+    pos.y = path.steps[1] as i32 / map.width;
+    index = map.get_index_xy(pos.x, pos.y);
+    map.blocked[index] = true;
+    viewshed.dirty = true;
+    Step::Moved
+}
+
+/// Steps in a random direction some of the time. Only open floor will do, so
+/// wanderers stay on their side of doors and never take the stairs.
+fn wander(map: &mut Map, pos: &mut Position, rng: &mut RandomNumberGenerator) -> bool {
+    let (delta_x, delta_y) = match rng.roll_dice(1, 8) {
+        1 => (-1, 0),
+        2 => (1, 0),
+        3 => (0, -1),
+        4 => (0, 1),
+        _ => return false,
+    };
+    let destination = map.get_index_xy(pos.x + delta_x, pos.y + delta_y);
+    if map.tiles[destination] != TileType::Floor || map.blocked[destination] {
+        return false;
+    }
+
+    let index = map.get_index_xy(pos.x, pos.y);
+    map.blocked[index] = false;
+    pos.x += delta_x;
+    pos.y += delta_y;
+    map.blocked[destination] = true;
+    true
+}
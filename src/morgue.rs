@@ -0,0 +1,57 @@
+use super::{CombatStats, GameLog, InBackpack, Map, Name, RunRecord};
+use crate::unique_file;
+use specs::prelude::*;
+use std::fs;
+use std::io::Write;
+
+const MORGUE_DIRECTORY: &str = "morgue";
+const MORGUE_LOG_LINES: usize = 20;
+
+/// Writes a plain-text account of the player's death and returns the path of
+/// the file.
+pub fn write_morgue(ecs: &World) -> std::io::Result<String> {
+    let player_entity = ecs.fetch::<Entity>();
+    let map = ecs.fetch::<Map>();
+    let record = ecs.fetch::<RunRecord>();
+    let log = ecs.fetch::<GameLog>();
+    let names = ecs.read_storage::<Name>();
+    let backpack = ecs.read_storage::<InBackpack>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+
+    let mut contents = String::new();
+    contents.push_str("Rusty Roguelike morgue file\n\n");
+    contents.push_str(&format!("Killed by: {}\n", record.cause_of_death()));
+    contents.push_str(&format!("Depth: {}\n", map.depth));
+    contents.push_str(&format!("Turns: {}\n", record.turns));
+
+    if let Some(stats) = combat_stats.get(*player_entity) {
+        contents.push_str(&format!(
+            "\nHP: {} / {}\nPower: {}\nDefense: {}\n",
+            stats.hp, stats.max_hp, stats.power, stats.defense
+        ));
+    }
+
+    contents.push_str("\nInventory:\n");
+    let inventory: Vec<&Name> = (&backpack, &names)
+        .join()
+        .filter(|(pack, _name)| pack.owner == *player_entity)
+        .map(|(_pack, name)| name)
+        .collect();
+    if inventory.is_empty() {
+        contents.push_str("  (nothing)\n");
+    }
+    for name in inventory {
+        contents.push_str(&format!("  {}\n", name.name));
+    }
+
+    contents.push_str("\nLast messages:\n");
+    let first_line = log.entries.len().saturating_sub(MORGUE_LOG_LINES);
+    for entry in log.entries[first_line..].iter() {
+        contents.push_str(&format!("  {}\n", entry));
+    }
+
+    fs::create_dir_all(MORGUE_DIRECTORY)?;
+    let (mut file, path) = unique_file::create(&format!("{}/morgue", MORGUE_DIRECTORY), "txt")?;
+    file.write_all(contents.as_bytes())?;
+    Ok(path)
+}
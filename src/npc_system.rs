@@ -0,0 +1,131 @@
+use super::{
+    CombatStats, GameLog, Healer, InBackpack, Name, Price, Purse, Shopkeeper, WantsToBuy,
+    WantsToTalk,
+};
+use specs::prelude::*;
+
+pub struct TalkSystem {}
+
+impl<'a> System<'a> for TalkSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        ReadExpect<'a, Entity>,
+        WriteExpect<'a, GameLog>,
+        Entities<'a>,
+        WriteStorage<'a, WantsToTalk>,
+        ReadStorage<'a, Name>,
+        ReadStorage<'a, Shopkeeper>,
+        ReadStorage<'a, Healer>,
+        WriteStorage<'a, CombatStats>,
+        ReadStorage<'a, InBackpack>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            player_entity,
+            mut gamelog,
+            entities,
+            mut wants_talk,
+            names,
+            shopkeepers,
+            healers,
+            mut combat_stats,
+            backpack,
+        ) = data;
+
+        for (entity, talk) in (&entities, &wants_talk).join() {
+            if entity != *player_entity {
+                continue;
+            }
+            let npc_name = names.get(talk.npc).map_or("Someone", |n| &n.name);
+
+            if healers.get(talk.npc).is_some() {
+                if let Some(stats) = combat_stats.get_mut(entity) {
+                    if stats.hp < stats.max_hp {
+                        stats.hp = stats.max_hp;
+                        gamelog
+                            .entries
+                            .push(format!("The {} tends to your wounds.", npc_name));
+                    } else {
+                        gamelog
+                            .entries
+                            .push(format!("The {} says you look well enough.", npc_name));
+                    }
+                }
+            } else if shopkeepers.get(talk.npc).is_some() {
+                // Walking into the shopkeeper opens the shop; passing by
+                // only gets a word in
+                let in_stock = backpack.join().any(|pack| pack.owner == talk.npc);
+                gamelog.entries.push(if in_stock {
+                    format!("The {} has wares for sale.", npc_name)
+                } else {
+                    format!("The {} has nothing left to sell.", npc_name)
+                });
+            } else {
+                gamelog
+                    .entries
+                    .push(format!("The {} greets you.", npc_name));
+            }
+        }
+        wants_talk.clear();
+    }
+}
+
+/// Pays for what the player picked in the shop, if the purse holds enough.
+pub struct BuySystem {}
+
+impl<'a> System<'a> for BuySystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        ReadExpect<'a, Entity>,
+        WriteExpect<'a, GameLog>,
+        Entities<'a>,
+        WriteStorage<'a, WantsToBuy>,
+        ReadStorage<'a, Name>,
+        WriteStorage<'a, Price>,
+        WriteStorage<'a, Purse>,
+        WriteStorage<'a, InBackpack>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            player_entity,
+            mut gamelog,
+            entities,
+            mut wants_buy,
+            names,
+            mut prices,
+            mut purses,
+            mut backpack,
+        ) = data;
+
+        for (entity, buy) in (&entities, &wants_buy).join() {
+            if entity != *player_entity {
+                continue;
+            }
+            let item_name = names.get(buy.item).map_or("something", |n| &n.name);
+            let price = prices.get(buy.item).map_or(0, |price| price.gold);
+            let purse = match purses.get_mut(entity) {
+                Some(purse) => purse,
+                None => continue,
+            };
+
+            if purse.gold < price {
+                gamelog.entries.push(format!(
+                    "The {} costs {} gold, and you have {}.",
+                    item_name, price, purse.gold
+                ));
+                continue;
+            }
+            purse.gold -= price;
+            prices.remove(buy.item);
+            backpack
+                .insert(buy.item, InBackpack { owner: entity })
+                .expect("Unable to insert backpack entry.");
+            gamelog
+                .entries
+                .push(format!("You buy the {} for {} gold.", item_name, price));
+        }
+        wants_buy.clear();
+    }
+}
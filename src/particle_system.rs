@@ -0,0 +1,137 @@
+use super::{ParticleLifetime, Position, Renderable};
+use rltk::{FontCharType, Rltk, RGB};
+use specs::prelude::*;
+
+/// Particles are drawn on top of everything else.
+const PARTICLE_ORDER: i32 = -1;
+
+struct ParticleRequest {
+    x: i32,
+    y: i32,
+    fg: RGB,
+    bg: RGB,
+    glyph: FontCharType,
+    lifetime: f32,
+}
+
+/// Collects particle requests from systems that cannot create entities
+/// themselves. They are turned into entities by `ParticleSpawnSystem`.
+pub struct ParticleBuilder {
+    requests: Vec<ParticleRequest>,
+}
+
+impl ParticleBuilder {
+    pub fn new() -> ParticleBuilder {
+        ParticleBuilder {
+            requests: Vec::new(),
+        }
+    }
+
+    pub fn request(
+        &mut self,
+        x: i32,
+        y: i32,
+        fg: RGB,
+        bg: RGB,
+        glyph: FontCharType,
+        lifetime: f32,
+    ) {
+        self.requests.push(ParticleRequest {
+            x,
+            y,
+            fg,
+            bg,
+            glyph,
+            lifetime,
+        });
+    }
+}
+
+impl Default for ParticleBuilder {
+    fn default() -> Self {
+        ParticleBuilder::new()
+    }
+}
+
+pub struct ParticleSpawnSystem {}
+
+impl<'a> System<'a> for ParticleSpawnSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Renderable>,
+        WriteStorage<'a, ParticleLifetime>,
+        WriteExpect<'a, ParticleBuilder>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, mut positions, mut renderables, mut particles, mut particle_builder) = data;
+
+        for new_particle in particle_builder.requests.iter() {
+            let particle = entities.create();
+            positions
+                .insert(
+                    particle,
+                    Position {
+                        x: new_particle.x,
+                        y: new_particle.y,
+                    },
+                )
+                .expect("Unable to insert position");
+            renderables
+                .insert(
+                    particle,
+                    Renderable {
+                        glyph: new_particle.glyph,
+                        fg: new_particle.fg,
+                        bg: new_particle.bg,
+                        render_order: PARTICLE_ORDER,
+                    },
+                )
+                .expect("Unable to insert renderable");
+            particles
+                .insert(
+                    particle,
+                    ParticleLifetime {
+                        lifetime_ms: new_particle.lifetime,
+                    },
+                )
+                .expect("Unable to insert lifetime");
+        }
+
+        particle_builder.requests.clear();
+    }
+}
+
+/// Ages every particle by the time the last frame took and stops drawing the
+/// expired ones. Runs every frame, whatever the run state.
+///
+/// Expired particles are only deleted by `clear_particles`, at a point that
+/// does not depend on the frame rate, so entity ids are handed out the same
+/// way when a replay is played back.
+pub fn cull_dead_particles(ecs: &mut World, ctx: &Rltk) {
+    let mut particles = ecs.write_storage::<ParticleLifetime>();
+    let mut renderables = ecs.write_storage::<Renderable>();
+    let entities = ecs.entities();
+    for (entity, particle) in (&entities, &mut particles).join() {
+        particle.lifetime_ms -= ctx.frame_time_ms;
+        if particle.lifetime_ms < 0.0 {
+            renderables.remove(entity);
+        }
+    }
+}
+
+/// Deletes every particle, expired or not. Called as the player's turn starts.
+pub fn clear_particles(ecs: &mut World) {
+    let particles: Vec<Entity> = {
+        let entities = ecs.entities();
+        let lifetimes = ecs.read_storage::<ParticleLifetime>();
+        (&entities, &lifetimes)
+            .join()
+            .map(|(entity, _lifetime)| entity)
+            .collect()
+    };
+    for particle in particles {
+        ecs.delete_entity(particle).expect("Particle will not die");
+    }
+}
@@ -1,22 +1,38 @@
-use rltk::{Point, Rltk, VirtualKeyCode};
+use rltk::{Point, Rltk};
 use specs::prelude::*;
-use std::cmp::{max, min};
 
 use super::{
-    CombatStats, GameLog, Item, Map, Player, Position, RunState, State, Viewshed, WantsToMelee,
-    WantsToPickupItem,
+    BlocksTile, CombatStats, EntityMoved, GameLog, Hidden, InBackpack, Item, Key, Map, Name, Npc,
+    Player, Position, RunState, Shopkeeper, State, Swimmer, TileType, Trap, Viewshed, WantsToMelee,
+    WantsToPickupItem, WantsToTalk,
 };
 
+use crate::auto_move;
 use crate::constants::{COORDINATE_X, COORDINATE_Y};
+use crate::keybindings::{Action, KeyBindings};
+use crate::map_file;
+use crate::replay::{self, Command};
+use crate::wizard::Wizard;
+
+/// A d20 roll must reach this to disarm a trap.
+const DISARM_DIFFICULTY: i32 = 8;
 
 pub fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
     let mut positions = ecs.write_storage::<Position>();
     let mut viewsheds = ecs.write_storage::<Viewshed>();
     let mut wants_to_melee = ecs.write_storage::<WantsToMelee>();
+    let mut wants_to_talk = ecs.write_storage::<WantsToTalk>();
+    let npcs = ecs.read_storage::<Npc>();
+    let swimmers = ecs.read_storage::<Swimmer>();
+    let blockers = ecs.read_storage::<BlocksTile>();
     let players = ecs.write_storage::<Player>();
     let combat_stats = ecs.write_storage::<CombatStats>();
+    let keys = ecs.read_storage::<Key>();
+    let backpack = ecs.read_storage::<InBackpack>();
     let entities = ecs.entities();
-    let map = ecs.fetch::<Map>();
+    let mut map = ecs.fetch_mut::<Map>();
+    let mut gamelog = ecs.fetch_mut::<GameLog>();
+    let mut entity_moved = ecs.write_storage::<EntityMoved>();
 
     for (entity, _player, pos, viewshed) in
         (&entities, &players, &mut positions, &mut viewsheds).join()
@@ -34,6 +50,18 @@ pub fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
 
         let destination_index = map.get_index_xy(sum_x_coordinates, sum_y_coordinates);
         for potential_target in map.tile_content[destination_index].iter() {
+            // Friendly folk get talked to, never attacked
+            if npcs.get(*potential_target).is_some() {
+                wants_to_talk
+                    .insert(
+                        entity,
+                        WantsToTalk {
+                            npc: *potential_target,
+                        },
+                    )
+                    .expect("Add talk failed");
+                continue;
+            }
             let target = combat_stats.get(*potential_target);
             if let Some(_target) = target {
                 wants_to_melee
@@ -47,11 +75,49 @@ pub fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
             }
         }
 
-        if !map.blocked[destination_index] {
-            pos.x = min(COORDINATE_X, max(0, sum_x_coordinates));
-            pos.y = min(COORDINATE_Y, max(0, sum_y_coordinates));
+        match map.tiles[destination_index] {
+            TileType::DoorClosed => {
+                map.tiles[destination_index] = TileType::DoorOpen;
+                viewshed.dirty = true;
+                gamelog.entries.push("You open the door.".to_string());
+                return;
+            }
+            TileType::DoorLocked => {
+                let key = (&entities, &keys, &backpack)
+                    .join()
+                    .find(|(_key_entity, _key, pack)| pack.owner == entity)
+                    .map(|(key_entity, _key, _pack)| key_entity);
+                match key {
+                    None => gamelog.entries.push("The door is locked.".to_string()),
+                    Some(key) => {
+                        entities.delete(key).expect("Delete failed");
+                        map.tiles[destination_index] = TileType::DoorOpen;
+                        viewshed.dirty = true;
+                        gamelog
+                            .entries
+                            .push("You unlock the door with your key.".to_string());
+                    }
+                }
+                return;
+            }
+            TileType::DeepWater if !swimmers.contains(entity) => {
+                gamelog
+                    .entries
+                    .push("The water is too deep to wade into.".to_string());
+                return;
+            }
+            _ => {}
+        }
+
+        let swims = swimmers.contains(entity) && map.is_open_water(destination_index, &blockers);
+        if !map.blocked[destination_index] || swims {
+            pos.x = sum_x_coordinates.clamp(0, COORDINATE_X);
+            pos.y = sum_y_coordinates.clamp(0, COORDINATE_Y);
 
             viewshed.dirty = true;
+            entity_moved
+                .insert(entity, EntityMoved {})
+                .expect("Unable to insert marker");
             let mut ppos = ecs.write_resource::<Point>();
             ppos.x = pos.x;
             ppos.y = pos.y;
@@ -93,44 +159,208 @@ fn get_item(ecs: &mut World) {
     }
 }
 
+/// Tries to disarm a known trap next to (or under) the player. Returns false
+/// when there is nothing to disarm, so no turn is spent.
+fn disarm_trap(ecs: &mut World) -> bool {
+    let player_pos = *ecs.fetch::<Point>();
+    let entities = ecs.entities();
+    let traps = ecs.read_storage::<Trap>();
+    let hidden = ecs.read_storage::<Hidden>();
+    let names = ecs.read_storage::<Name>();
+    let positions = ecs.read_storage::<Position>();
+    let mut gamelog = ecs.fetch_mut::<GameLog>();
+
+    let target = (&entities, &traps, &positions, !&hidden)
+        .join()
+        .find(|(_entity, _trap, pos, _hidden)| {
+            i32::abs(pos.x - player_pos.x) <= 1 && i32::abs(pos.y - player_pos.y) <= 1
+        })
+        .map(|(entity, _trap, _pos, _hidden)| entity);
+
+    match target {
+        None => {
+            gamelog
+                .entries
+                .push("There is no known trap nearby to disarm.".to_string());
+            false
+        }
+        Some(trap) => {
+            let roll = ecs
+                .write_resource::<rltk::RandomNumberGenerator>()
+                .roll_dice(1, 20);
+            let trap_name = names.get(trap).map_or("trap", |n| &n.name);
+            if roll >= DISARM_DIFFICULTY {
+                gamelog
+                    .entries
+                    .push(format!("You disarm the {}.", trap_name));
+                entities.delete(trap).expect("Delete failed");
+            } else {
+                gamelog
+                    .entries
+                    .push(format!("You fail to disarm the {}.", trap_name));
+            }
+            true
+        }
+    }
+}
+
 pub fn player_input(gs: &mut State, ctx: &mut Rltk) -> RunState {
-    // Player movement
-    match ctx.key {
-        None => return RunState::AwaitingInput,
-        Some(key) => match key {
-            VirtualKeyCode::Left
-            | VirtualKeyCode::Numpad4
-            | VirtualKeyCode::H
-            | VirtualKeyCode::A => try_move_player(-1, 0, &mut gs.ecs),
-
-            VirtualKeyCode::Right
-            | VirtualKeyCode::Numpad6
-            | VirtualKeyCode::L
-            | VirtualKeyCode::D => try_move_player(1, 0, &mut gs.ecs),
-
-            VirtualKeyCode::Up
-            | VirtualKeyCode::Numpad8
-            | VirtualKeyCode::K
-            | VirtualKeyCode::W => try_move_player(0, -1, &mut gs.ecs),
-
-            VirtualKeyCode::Down
-            | VirtualKeyCode::Numpad2
-            | VirtualKeyCode::J
-            | VirtualKeyCode::S => try_move_player(0, 1, &mut gs.ecs),
-
-            // Diagonals
-            VirtualKeyCode::Numpad9 | VirtualKeyCode::Y => try_move_player(1, -1, &mut gs.ecs),
-            VirtualKeyCode::Numpad7 | VirtualKeyCode::U => try_move_player(-1, -1, &mut gs.ecs),
-            VirtualKeyCode::Numpad3 | VirtualKeyCode::N => try_move_player(1, 1, &mut gs.ecs),
-            VirtualKeyCode::Numpad1 | VirtualKeyCode::B => try_move_player(-1, 1, &mut gs.ecs),
-
-            // Picking up items
-            VirtualKeyCode::G => get_item(&mut gs.ecs),
-            VirtualKeyCode::I => return RunState::ShowInventory,
-            VirtualKeyCode::X => return RunState::ShowDropItem,
-
-            _ => return RunState::AwaitingInput,
+    if replay::is_playing(&gs.ecs) {
+        return match replay::next_command(&gs.ecs) {
+            Some(command) => apply_command(&mut gs.ecs, command),
+            None => RunState::AwaitingInput,
+        };
+    }
+
+    let command = match read_command(gs, ctx) {
+        Ok(command) => command,
+        Err(run_state) => return run_state,
+    };
+    replay::record(&gs.ecs, command);
+    apply_command(&mut gs.ecs, command)
+}
+
+/// Turns the mouse and keyboard into a command. Input that does not change
+/// the game gets the run state to switch to instead.
+fn read_command(gs: &mut State, ctx: &mut Rltk) -> Result<Command, RunState> {
+    // Travel to a clicked tile
+    if ctx.left_click {
+        let (x, y) = ctx.mouse_pos();
+        return Ok(Command::Travel { x, y });
+    }
+
+    let action = match ctx.key {
+        None => return Err(RunState::AwaitingInput),
+        Some(key) => match gs.ecs.fetch::<KeyBindings>().action_for(key) {
+            None => return Err(RunState::AwaitingInput),
+            Some(action) => action,
         },
+    };
+
+    let command = match action {
+        // Player movement, or running when shift is held
+        Action::MoveLeft
+        | Action::MoveRight
+        | Action::MoveUp
+        | Action::MoveDown
+        | Action::MoveUpRight
+        | Action::MoveUpLeft
+        | Action::MoveDownRight
+        | Action::MoveDownLeft => {
+            let (delta_x, delta_y) = action.direction().unwrap();
+            if ctx.shift {
+                Command::Run { delta_x, delta_y }
+            } else {
+                Command::Move { delta_x, delta_y }
+            }
+        }
+        Action::PickUp => Command::PickUp,
+        Action::Inventory => Command::Inventory,
+        Action::DropItem => Command::DropItem,
+        Action::Wait => Command::Wait,
+        Action::Explore => Command::Explore,
+        Action::Rest => Command::Rest,
+        Action::Disarm => Command::Disarm,
+        Action::Descend => Command::Descend,
+        Action::Console => {
+            if gs.ecs.fetch::<Wizard>().enabled {
+                return Err(RunState::WizardConsole);
+            }
+            return Err(RunState::AwaitingInput);
+        }
+        Action::ExportMap => {
+            let message = match map_file::export_for_report(&gs.ecs) {
+                Ok(path) => format!("Level saved to {}", path),
+                Err(error) => error,
+            };
+            gs.ecs.write_resource::<GameLog>().entries.push(message);
+            return Err(RunState::AwaitingInput);
+        }
+        Action::Help => return Err(RunState::ShowHelp),
+    };
+    Ok(command)
+}
+
+/// The shopkeeper the player just walked into, if any. Looking at the
+/// wares takes no time, so the talk is called off.
+fn visited_shop(ecs: &mut World) -> Option<Entity> {
+    let player_entity = *ecs.fetch::<Entity>();
+    let mut wants_to_talk = ecs.write_storage::<WantsToTalk>();
+    let shopkeepers = ecs.read_storage::<Shopkeeper>();
+    let shopkeeper = wants_to_talk.get(player_entity)?.npc;
+    if !shopkeepers.contains(shopkeeper) {
+        return None;
+    }
+    wants_to_talk.remove(player_entity);
+    Some(shopkeeper)
+}
+
+/// True when the player stands on the stairs down.
+fn try_descend(ecs: &mut World) -> bool {
+    let player_pos = ecs.fetch::<Point>();
+    let map = ecs.fetch::<Map>();
+    let index = map.get_index_xy(player_pos.x, player_pos.y);
+    if map.tiles[index] == TileType::DownStairs {
+        return true;
+    }
+    ecs.fetch_mut::<GameLog>()
+        .entries
+        .push("There is no way down here.".to_string());
+    false
+}
+
+/// Carries out a command given from the map.
+pub fn apply_command(ecs: &mut World, command: Command) -> RunState {
+    match command {
+        Command::Move { delta_x, delta_y } => {
+            try_move_player(delta_x, delta_y, ecs);
+            if let Some(shopkeeper) = visited_shop(ecs) {
+                return RunState::ShowShop { shopkeeper };
+            }
+        }
+        Command::Run { delta_x, delta_y } => {
+            auto_move::start_run(ecs, delta_x, delta_y);
+            return RunState::AwaitingInput;
+        }
+        Command::Travel { x, y } => {
+            auto_move::start_travel(ecs, x, y);
+            return RunState::AwaitingInput;
+        }
+
+        // Picking up items
+        Command::PickUp => get_item(ecs),
+        Command::Inventory => return RunState::ShowInventory,
+        Command::DropItem => return RunState::ShowDropItem,
+
+        // Skip a turn
+        Command::Wait => {}
+
+        // Automatic movement
+        Command::Explore => {
+            auto_move::start_explore(ecs);
+            return RunState::AwaitingInput;
+        }
+        Command::Rest => {
+            auto_move::start_rest(ecs);
+            return RunState::AwaitingInput;
+        }
+
+        Command::Disarm => {
+            if !disarm_trap(ecs) {
+                return RunState::AwaitingInput;
+            }
+        }
+        Command::Descend => {
+            if try_descend(ecs) {
+                return RunState::NextLevel;
+            }
+            return RunState::AwaitingInput;
+        }
+
+        // Menu answers and interruptions are handled where they happen
+        Command::Select { .. } | Command::Cancel | Command::Interrupt { .. } => {
+            return RunState::AwaitingInput
+        }
     }
     RunState::PlayerTurn
 }
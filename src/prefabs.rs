@@ -0,0 +1,393 @@
+use super::{Map, Rect, TileType};
+use rltk::RandomNumberGenerator;
+
+/// One in this many levels gets a vault stamped into one of its rooms.
+const VAULT_CHANCE: i32 = 2;
+/// One in this many levels gets a sectional overlay in a corner.
+const SECTIONAL_CHANCE: i32 = 3;
+
+/// Where a prefab goes when a level is generated.
+#[derive(PartialEq, Copy, Clone)]
+pub enum Placement {
+    /// Replaces the generated level entirely on the given depth.
+    Level { depth: i32 },
+    /// Fills the inside of a generated room that is big enough for it.
+    RoomVault,
+    /// Overlays one corner of the level and is tunnelled to the nearest room.
+    Sectional,
+}
+
+/// Characters that place something on a floor tile when stamped.
+const SPAWN_LEGEND: [(char, &str); 8] = [
+    ('o', "Orc"),
+    ('g', "Goblin"),
+    ('!', "Health Potion"),
+    ('-', "Key"),
+    ('^', "Trap"),
+    ('S', "Shopkeeper"),
+    ('H', "Healer"),
+    ('V', "Villager"),
+];
+
+/// A hand-made map section. Templates use this legend, which map files
+/// share:
+///
+/// `#` wall, `.` floor, `+` closed door, `/` open door, `=` locked door,
+/// `>` stairs down, `~` shallow water, `W` deep water, `%` lava, `:` rubble,
+/// `@` player start (level prefabs), `o` orc, `g` goblin, `!` health potion,
+/// `-` key, `^` hidden trap, `S` shopkeeper, `H` healer, `V` villager, and a
+/// space to keep whatever was generated underneath.
+pub struct Prefab {
+    pub placement: Placement,
+    template: &'static str,
+}
+
+const PREFABS: [Prefab; 5] = [
+    // The Town, where every run starts
+    Prefab {
+        placement: Placement::Level { depth: 0 },
+        template: "
+######################################################################
+#....................................................................#
+#..############.................................#################....#
+#..#..........#.........~~~~~~..................#...............#....#
+#..#....S.....#.......~~~WWWW~~~................#.......H.......#....#
+#..#..........#.......~~WWWWWW~~................#...............#....#
+#..#..........#........~~~WWW~~.................#...............#....#
+#..######+#####..........~~~~...................########+########....#
+#....................................................................#
+#.......................V............................................#
+#....................................................................#
+#..........#######..............@..................#######...........#
+#..........#.....#.................................#.....#...........#
+#..........#..V..+.................................+.....#...........#
+#..........#.....#..................V..............#.....#...........#
+#..........#######.................................#######...........#
+#....................................................................#
+#....................................................................#
+#...............................:::::................................#
+#..............................:#####:...............................#
+#..............................:#...#:...............................#
+#..............................:#.>.#:...............................#
+#..............................:##.##:...............................#
+#....................................................................#
+######################################################################
+",
+    },
+    // Goblin Den
+    Prefab {
+        placement: Placement::RoomVault,
+        template: "
+.......
+.##.##.
+.#g!g#.
+.#####.
+.......
+",
+    },
+    // Trapped Larder
+    Prefab {
+        placement: Placement::RoomVault,
+        template: "
+.....
+.^!^.
+.....
+",
+    },
+    // Orc Guard Post
+    Prefab {
+        placement: Placement::Sectional,
+        template: "
+#########
+#.......#
+#.o...!.#
+#.......+
+#########
+",
+    },
+    // The Pit
+    Prefab {
+        placement: Placement::Level { depth: 3 },
+        template: "
+###########################
+#.........#.....#.........#
+#..o...!..+..@..+..!...g..#
+#.........#.....#.........#
+#####+#####.....#####+#####
+#.........#.....#.........#
+#..^...g..#.....#..o...^..#
+#.........##+####.........#
+#.............^..........>#
+###########################
+",
+    },
+];
+
+impl Prefab {
+    pub fn rows(&self) -> Vec<Vec<char>> {
+        self.template
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.chars().collect())
+            .collect()
+    }
+
+    fn size(&self) -> (i32, i32) {
+        size(&self.rows())
+    }
+}
+
+fn size(rows: &[Vec<char>]) -> (i32, i32) {
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    (width as i32, rows.len() as i32)
+}
+
+/// The legend character for a tile.
+pub fn tile_char(tile: TileType) -> char {
+    match tile {
+        TileType::Wall => '#',
+        TileType::Floor => '.',
+        TileType::DoorClosed => '+',
+        TileType::DoorOpen => '/',
+        TileType::DoorLocked => '=',
+        TileType::DownStairs => '>',
+        TileType::ShallowWater => '~',
+        TileType::DeepWater => 'W',
+        TileType::Lava => '%',
+        TileType::Rubble => ':',
+    }
+}
+
+/// The legend character that spawns the thing called `name`, if any.
+pub fn spawn_char(name: &str) -> Option<char> {
+    SPAWN_LEGEND
+        .iter()
+        .find(|(_glyph, spawn)| *spawn == name)
+        .map(|(glyph, _spawn)| *glyph)
+}
+
+/// True for the player start and the characters of the spawn legend.
+pub fn is_entity_char(glyph: char) -> bool {
+    glyph == '@' || spawn_name(glyph).is_some()
+}
+
+/// What a character of the spawn legend places, if anything.
+fn spawn_name(glyph: char) -> Option<&'static str> {
+    SPAWN_LEGEND
+        .iter()
+        .find(|(spawn_glyph, _spawn)| *spawn_glyph == glyph)
+        .map(|(_glyph, spawn)| *spawn)
+}
+
+/// What stamping a prefab left behind besides tiles.
+struct Stamp {
+    spawns: Vec<(usize, String)>,
+    start: Option<usize>,
+    doors: Vec<(i32, i32)>,
+}
+
+/// Writes `rows` into the map with their top-left corner at `x`, `y`.
+fn stamp(map: &mut Map, rows: &[Vec<char>], x: i32, y: i32) -> Stamp {
+    let mut stamp = Stamp {
+        spawns: Vec::new(),
+        start: None,
+        doors: Vec::new(),
+    };
+
+    for (dy, row) in rows.iter().enumerate() {
+        for (dx, glyph) in row.iter().enumerate() {
+            let (tile_x, tile_y) = (x + dx as i32, y + dy as i32);
+            if tile_x < 1 || tile_x > map.width - 2 || tile_y < 1 || tile_y > map.height - 2 {
+                continue;
+            }
+            let index = map.get_index_xy(tile_x, tile_y);
+            let (tile, spawn) = match glyph {
+                '#' => (TileType::Wall, None),
+                '+' => {
+                    stamp.doors.push((tile_x, tile_y));
+                    (TileType::DoorClosed, None)
+                }
+                '/' => (TileType::DoorOpen, None),
+                '=' => (TileType::DoorLocked, None),
+                '>' => (TileType::DownStairs, None),
+                '~' => (TileType::ShallowWater, None),
+                'W' => (TileType::DeepWater, None),
+                '%' => (TileType::Lava, None),
+                ':' => (TileType::Rubble, None),
+                '@' => {
+                    stamp.start = Some(index);
+                    (TileType::Floor, None)
+                }
+                '.' => (TileType::Floor, None),
+                _ => match spawn_name(*glyph) {
+                    Some(name) => (TileType::Floor, Some(name)),
+                    None => continue,
+                },
+            };
+            map.tiles[index] = tile;
+            if let Some(name) = spawn {
+                stamp.spawns.push((index, name.to_string()));
+            }
+        }
+    }
+    stamp
+}
+
+/// True when every walkable tile can be reached from the start room.
+fn connected(map: &mut Map) -> bool {
+    let (start_x, start_y) = map.rooms[0].center();
+    let start = map.get_index_xy(start_x, start_y);
+    let reachable = map.reachable_from(start);
+    map.tiles
+        .iter()
+        .zip(reachable.iter())
+        .all(|(tile, reachable)| !tile.is_walkable() || *reachable)
+}
+
+/// The level prefab for `depth`, if there is one.
+pub fn level_prefab(depth: i32) -> Option<&'static Prefab> {
+    PREFABS
+        .iter()
+        .find(|prefab| prefab.placement == Placement::Level { depth })
+}
+
+/// Builds the whole map from the rows of a level prefab or map file, centred
+/// on the screen, with the player start marked. The `overlay` places the
+/// player and spawns, by their legend character, at a column and row of
+/// `rows` without changing the tile there.
+pub fn build_level(map: &mut Map, rows: &[Vec<char>], overlay: &[(i32, i32, char)]) {
+    let (width, height) = size(rows);
+    let x = (map.width - width).max(0) / 2;
+    let y = (map.height - height).max(0) / 2;
+    let mut stamp = stamp(map, rows, x, y);
+
+    for (column, row, glyph) in overlay.iter() {
+        let (tile_x, tile_y) = (x + column, y + row);
+        if tile_x < 1 || tile_x > map.width - 2 || tile_y < 1 || tile_y > map.height - 2 {
+            continue;
+        }
+        let index = map.get_index_xy(tile_x, tile_y);
+        if *glyph == '@' {
+            stamp.start = Some(index);
+        } else if let Some(name) = spawn_name(*glyph) {
+            stamp.spawns.push((index, name.to_string()));
+        }
+    }
+
+    let (start_x, start_y) = match stamp.start {
+        Some(index) => (index as i32 % map.width, index as i32 / map.width),
+        None => (x + width / 2, y + height / 2),
+    };
+    map.mark_start(start_x, start_y);
+    map.spawns.extend(stamp.spawns);
+    map.take_snapshot();
+}
+
+/// Maybe puts a vault inside one of the rooms, other than the start room.
+/// The room is taken out of `map.rooms` so it is not filled at random too.
+pub fn place_vault(map: &mut Map, rng: &mut RandomNumberGenerator) {
+    if rng.roll_dice(1, VAULT_CHANCE) != 1 {
+        return;
+    }
+    let vaults: Vec<&Prefab> = PREFABS
+        .iter()
+        .filter(|prefab| prefab.placement == Placement::RoomVault)
+        .collect();
+    let vault = match rng.random_slice_entry(&vaults) {
+        Some(vault) => *vault,
+        None => return,
+    };
+
+    let (width, height) = vault.size();
+    let mut candidates: Vec<usize> = (1..map.rooms.len())
+        .filter(|i| {
+            let room = &map.rooms[*i];
+            room.x2 - room.x1 >= width && room.y2 - room.y1 >= height
+        })
+        .collect();
+
+    while !candidates.is_empty() {
+        let pick = (rng.roll_dice(1, candidates.len() as i32) - 1) as usize;
+        let room_index = candidates.remove(pick);
+        let room = &map.rooms[room_index];
+        let x = room.x1 + 1 + (room.x2 - room.x1 - width) / 2;
+        let y = room.y1 + 1 + (room.y2 - room.y1 - height) / 2;
+        let old_tiles = map.tiles.clone();
+
+        let stamp = stamp(map, &vault.rows(), x, y);
+        if connected(map) {
+            map.spawns.extend(stamp.spawns);
+            map.rooms.remove(room_index);
+            map.take_snapshot();
+            return;
+        }
+        map.tiles = old_tiles;
+    }
+}
+
+/// Maybe overlays a sectional prefab on a corner of the map and tunnels from
+/// its door to the nearest room, keeping it only if nothing was cut off.
+pub fn place_sectional(map: &mut Map, rng: &mut RandomNumberGenerator) {
+    if rng.roll_dice(1, SECTIONAL_CHANCE) != 1 {
+        return;
+    }
+    let sectionals: Vec<&Prefab> = PREFABS
+        .iter()
+        .filter(|prefab| prefab.placement == Placement::Sectional)
+        .collect();
+    let sectional = match rng.random_slice_entry(&sectionals) {
+        Some(sectional) => *sectional,
+        None => return,
+    };
+
+    let (width, height) = sectional.size();
+    let mut corners = vec![
+        (1, 1),
+        (map.width - width - 1, 1),
+        (1, map.height - height - 1),
+        (map.width - width - 1, map.height - height - 1),
+    ];
+
+    while !corners.is_empty() {
+        let pick = (rng.roll_dice(1, corners.len() as i32) - 1) as usize;
+        let (x, y) = corners.remove(pick);
+        let area = Rect::new(x, y, width - 1, height - 1);
+        // Never bury the player start
+        if area.intersect(&map.rooms[0]) {
+            continue;
+        }
+        let old_tiles = map.tiles.clone();
+
+        let stamp = stamp(map, &sectional.rows(), x, y);
+        let entrance = stamp.doors.first().map(|(door_x, door_y)| {
+            // The tile just outside the door
+            if *door_x == area.x1 {
+                (door_x - 1, *door_y)
+            } else if *door_x == area.x2 {
+                (door_x + 1, *door_y)
+            } else if *door_y == area.y1 {
+                (*door_x, door_y - 1)
+            } else {
+                (*door_x, door_y + 1)
+            }
+        });
+        let nearest_room = entrance.and_then(|(entrance_x, entrance_y)| {
+            map.rooms
+                .iter()
+                .filter(|room| !room.intersect(&area))
+                .map(|room| room.center())
+                .min_by_key(|(room_x, room_y)| {
+                    (room_x - entrance_x).abs() + (room_y - entrance_y).abs()
+                })
+        });
+        if let (Some(entrance), Some(room_center)) = (entrance, nearest_room) {
+            map.tunnel_between(entrance, room_center, rng);
+            if connected(map) {
+                map.spawns.extend(stamp.spawns);
+                map.take_snapshot();
+                return;
+            }
+        }
+        map.tiles = old_tiles;
+    }
+}
@@ -0,0 +1,592 @@
+use super::{
+    auto_move::AutoMove, gamelog::GameLog, run_record::RunRecord, Alerted, CombatStats, Hidden,
+    InBackpack, Item, Map, Monster, Name, ParticleLifetime, Player, Position, Price, Purse, Trap,
+};
+use crate::gui::ItemMenuResult;
+use crate::{auto_move, damage_system, player, unique_file, RunState, State};
+use rltk::{Point, Rltk};
+use specs::prelude::*;
+use std::fs::{self, File};
+use std::io::Write;
+
+const REPLAY_DIRECTORY: &str = "replays";
+
+/// Everything the player can do that changes the game. Screens that only
+/// show information, like the help, are not recorded.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Command {
+    Move { delta_x: i32, delta_y: i32 },
+    Run { delta_x: i32, delta_y: i32 },
+    Travel { x: i32, y: i32 },
+    PickUp,
+    Inventory,
+    DropItem,
+    Select { index: usize },
+    Cancel,
+    Wait,
+    Explore,
+    Rest,
+    Disarm,
+    Descend,
+    Interrupt { turn: i32 },
+}
+
+impl Command {
+    fn to_line(self) -> String {
+        match self {
+            Command::Move { delta_x, delta_y } => format!("move {} {}", delta_x, delta_y),
+            Command::Run { delta_x, delta_y } => format!("run {} {}", delta_x, delta_y),
+            Command::Travel { x, y } => format!("travel {} {}", x, y),
+            Command::PickUp => "pick_up".to_string(),
+            Command::Inventory => "inventory".to_string(),
+            Command::DropItem => "drop_item".to_string(),
+            Command::Select { index } => format!("select {}", index),
+            Command::Cancel => "cancel".to_string(),
+            Command::Wait => "wait".to_string(),
+            Command::Explore => "explore".to_string(),
+            Command::Rest => "rest".to_string(),
+            Command::Disarm => "disarm".to_string(),
+            Command::Descend => "descend".to_string(),
+            Command::Interrupt { turn } => format!("interrupt {}", turn),
+        }
+    }
+
+    fn from_words(words: &[&str]) -> Option<Command> {
+        let number = |i: usize| -> Option<i32> { words.get(i)?.parse().ok() };
+        let command = match words.first().copied()? {
+            "move" => Command::Move {
+                delta_x: number(1)?,
+                delta_y: number(2)?,
+            },
+            "run" => Command::Run {
+                delta_x: number(1)?,
+                delta_y: number(2)?,
+            },
+            "travel" => Command::Travel {
+                x: number(1)?,
+                y: number(2)?,
+            },
+            "pick_up" => Command::PickUp,
+            "inventory" => Command::Inventory,
+            "drop_item" => Command::DropItem,
+            "select" => Command::Select {
+                index: words.get(1)?.parse().ok()?,
+            },
+            "cancel" => Command::Cancel,
+            "wait" => Command::Wait,
+            "explore" => Command::Explore,
+            "rest" => Command::Rest,
+            "disarm" => Command::Disarm,
+            "descend" => Command::Descend,
+            "interrupt" => Command::Interrupt { turn: number(1)? },
+            _ => return None,
+        };
+        Some(command)
+    }
+
+    /// Commands that start a turn from the map, as opposed to answering a
+    /// menu or cutting automatic movement short.
+    fn is_checkpoint(self) -> bool {
+        !matches!(
+            self,
+            Command::Select { .. } | Command::Cancel | Command::Interrupt { .. }
+        )
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum Entry {
+    Command(Command),
+    Checksum(u64),
+}
+
+/// Either writes the current game to a replay file or feeds one back in.
+pub enum Replay {
+    Recording {
+        seed: u64,
+        file: Option<File>,
+        failed: bool,
+    },
+    Playing {
+        entries: Vec<Entry>,
+        next: usize,
+        checksums: usize,
+    },
+    /// How many checksums a playback matched, or where it went wrong.
+    Finished { result: Result<usize, String> },
+}
+
+impl Replay {
+    /// Records a new game. The file is only created once the first command
+    /// comes in, so games abandoned at the main menu leave nothing behind.
+    pub fn record(seed: u64) -> Replay {
+        Replay::Recording {
+            seed,
+            file: None,
+            failed: false,
+        }
+    }
+
+    /// Loads a replay file, returning the seed it was recorded with.
+    pub fn load(path: &str) -> Result<(u64, Replay), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| format!("Unable to read {}: {}", path, error))?;
+
+        let mut seed = None;
+        let mut entries = Vec::new();
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let entry = match words[0] {
+                "seed" => {
+                    seed = words.get(1).and_then(|word| word.parse().ok());
+                    continue;
+                }
+                "checksum" => words
+                    .get(1)
+                    .and_then(|word| u64::from_str_radix(word, 16).ok())
+                    .map(Entry::Checksum),
+                _ => Command::from_words(&words).map(Entry::Command),
+            };
+            match entry {
+                Some(entry) => entries.push(entry),
+                None => {
+                    return Err(format!(
+                        "Replay line {}: cannot read '{}'",
+                        line_number + 1,
+                        line
+                    ))
+                }
+            }
+        }
+
+        let seed = seed.ok_or_else(|| format!("{} has no seed line", path))?;
+        Ok((
+            seed,
+            Replay::Playing {
+                entries,
+                next: 0,
+                checksums: 0,
+            },
+        ))
+    }
+}
+
+pub fn is_playing(ecs: &World) -> bool {
+    matches!(*ecs.fetch::<Replay>(), Replay::Playing { .. })
+}
+
+/// Writes a command to the replay file, preceded by a checksum of the world
+/// it is about to be applied to.
+pub fn record(ecs: &World, command: Command) {
+    let checksum = if command.is_checkpoint() {
+        Some(world_checksum(ecs))
+    } else {
+        None
+    };
+    let mut replay = ecs.write_resource::<Replay>();
+    if let Some(checksum) = checksum {
+        write_line(&mut replay, &format!("checksum {:016x}", checksum));
+    }
+    write_line(&mut replay, &command.to_line());
+}
+
+/// Seals the replay once the game is over: a recording gets the final
+/// checksum, a playback checks it.
+pub fn finish(ecs: &World) {
+    if is_playing(ecs) {
+        next_entry(ecs);
+        if is_playing(ecs) {
+            end_playback(ecs);
+        }
+        return;
+    }
+    let checksum = world_checksum(ecs);
+    let mut replay = ecs.write_resource::<Replay>();
+    write_line(&mut replay, &format!("checksum {:016x}", checksum));
+}
+
+/// Ends recording or playback early, when the game stops being reproducible.
+pub fn abandon(ecs: &World, reason: &str) {
+    let mut replay = ecs.write_resource::<Replay>();
+    // Nothing to explain when no command was recorded yet
+    if let Replay::Recording { file: Some(_), .. } = *replay {
+        write_line(&mut replay, &format!("# The replay ends here: {}", reason));
+    }
+    if !matches!(*replay, Replay::Finished { .. }) {
+        *replay = Replay::Finished {
+            result: Err(format!("The replay was abandoned: {}", reason)),
+        };
+    }
+}
+
+/// The next recorded command, after checking any checksum in front of it.
+/// Returns `None` when not playing back.
+pub fn next_command(ecs: &World) -> Option<Command> {
+    match next_entry(ecs) {
+        Some(Entry::Command(command)) => Some(command),
+        _ => None,
+    }
+}
+
+/// Whether automatic movement is cut short this turn. While recording that
+/// is a key press; when playing back, the recording says so.
+pub fn interrupted(ecs: &World, key_pressed: bool) -> bool {
+    if *ecs.fetch::<AutoMove>() == AutoMove::Idle {
+        return false;
+    }
+    let turn = ecs.fetch::<RunRecord>().turns;
+
+    if is_playing(ecs) {
+        let mut replay = ecs.write_resource::<Replay>();
+        if let Replay::Playing { entries, next, .. } = &mut *replay {
+            if entries.get(*next) == Some(&Entry::Command(Command::Interrupt { turn })) {
+                *next += 1;
+                return true;
+            }
+        }
+        return false;
+    }
+
+    if key_pressed {
+        record(ecs, Command::Interrupt { turn });
+    }
+    key_pressed
+}
+
+/// Answers a menu of the items `owner` carries: from the recording when
+/// playing back, otherwise from the player, whose answer is recorded.
+pub fn menu_choice<F>(
+    gs: &mut State,
+    ctx: &mut Rltk,
+    owner: Entity,
+    menu: F,
+) -> (ItemMenuResult, Option<Entity>)
+where
+    F: FnOnce(&mut State, &mut Rltk) -> (ItemMenuResult, Option<Entity>),
+{
+    let result = menu(gs, ctx);
+
+    if is_playing(&gs.ecs) {
+        return match next_command(&gs.ecs) {
+            Some(command) => chosen_item(&gs.ecs, owner, command),
+            None => (ItemMenuResult::NoResponse, None),
+        };
+    }
+
+    match result {
+        (ItemMenuResult::Selected, Some(item)) => {
+            let index = carried_items(&gs.ecs, owner)
+                .iter()
+                .position(|entity| *entity == item)
+                .expect("Selected item is not in the menu");
+            record(&gs.ecs, Command::Select { index });
+        }
+        (ItemMenuResult::Cancel, _) => record(&gs.ecs, Command::Cancel),
+        _ => {}
+    }
+    result
+}
+
+/// The answer to a menu of the items `owner` carries that a recorded
+/// command stands for.
+fn chosen_item(ecs: &World, owner: Entity, command: Command) -> (ItemMenuResult, Option<Entity>) {
+    match command {
+        Command::Select { index } => match carried_items(ecs, owner).get(index) {
+            Some(item) => (ItemMenuResult::Selected, Some(*item)),
+            None => (ItemMenuResult::Cancel, None),
+        },
+        _ => (ItemMenuResult::Cancel, None),
+    }
+}
+
+/// Plays the game without a window, taking the player's commands from
+/// `next_command` until it runs out or the player dies.
+pub fn play_headless<F>(gs: &mut State, mut next_command: F)
+where
+    F: FnMut(&World) -> Option<Command>,
+{
+    loop {
+        let run_state = *gs.ecs.fetch::<RunState>();
+        let new_run_state = match run_state {
+            RunState::AwaitingInput => {
+                let interrupted = interrupted(&gs.ecs, false);
+                match auto_move::auto_move_step(&mut gs.ecs, interrupted) {
+                    Some(run_state) => run_state,
+                    None => match next_command(&gs.ecs) {
+                        Some(command) => player::apply_command(&mut gs.ecs, command),
+                        None => return,
+                    },
+                }
+            }
+            RunState::ShowInventory | RunState::ShowDropItem => match next_command(&gs.ecs) {
+                Some(command) => {
+                    let player_entity = *gs.ecs.fetch::<Entity>();
+                    let result = chosen_item(&gs.ecs, player_entity, command);
+                    gs.item_menu_result(run_state, result)
+                }
+                None => return,
+            },
+            RunState::ShowShop { shopkeeper } => match next_command(&gs.ecs) {
+                Some(command) => {
+                    let result = chosen_item(&gs.ecs, shopkeeper, command);
+                    gs.item_menu_result(run_state, result)
+                }
+                None => return,
+            },
+            RunState::PreRun
+            | RunState::PlayerTurn
+            | RunState::MonsterTurn
+            | RunState::NextLevel => gs.advance(run_state),
+            // The game is over, or waiting on a screen no command leads to
+            _ => return,
+        };
+        *gs.ecs.write_resource::<RunState>() = new_run_state;
+        damage_system::delete_the_dead(&mut gs.ecs);
+    }
+}
+
+/// Plays a replay file through without a window. Returns how many checksums
+/// matched, or where the game stopped matching its recording.
+pub fn check(path: &str) -> Result<usize, String> {
+    let (seed, replay) = Replay::load(path)?;
+    let mut gs = State::new();
+    gs.new_game(seed);
+    gs.ecs.insert(replay);
+    gs.ecs.insert(RunState::PreRun);
+
+    play_headless(&mut gs, next_command);
+    if is_playing(&gs.ecs) {
+        return Err(format!("{} stopped before its last command", path));
+    }
+    let replay = gs.ecs.fetch::<Replay>();
+    match &*replay {
+        Replay::Finished { result } => result.clone(),
+        _ => Err(format!("{} did not finish", path)),
+    }
+}
+
+/// Checks checksums until it reaches a command, which it hands back.
+fn next_entry(ecs: &World) -> Option<Entry> {
+    loop {
+        let entry = {
+            let mut replay = ecs.write_resource::<Replay>();
+            match &mut *replay {
+                Replay::Playing { entries, next, .. } => {
+                    let entry = entries.get(*next).copied();
+                    *next += 1;
+                    entry
+                }
+                _ => return None,
+            }
+        };
+
+        match entry {
+            None => {
+                end_playback(ecs);
+                return None;
+            }
+            Some(Entry::Checksum(expected)) => {
+                let actual = world_checksum(ecs);
+                let mut replay = ecs.write_resource::<Replay>();
+                if let Replay::Playing {
+                    next, checksums, ..
+                } = &mut *replay
+                {
+                    if actual != expected {
+                        let message = format!(
+                            "Replay diverged before entry {}: expected checksum {:016x}, got {:016x}",
+                            *next, expected, actual
+                        );
+                        drop(replay);
+                        stop_playback(ecs, Err(message));
+                        return None;
+                    }
+                    *checksums += 1;
+                }
+            }
+            Some(command) => return Some(command),
+        }
+    }
+}
+
+fn end_playback(ecs: &World) {
+    let checksums = match &*ecs.fetch::<Replay>() {
+        Replay::Playing { checksums, .. } => *checksums,
+        _ => return,
+    };
+    stop_playback(ecs, Ok(checksums));
+}
+
+fn stop_playback(ecs: &World, result: Result<usize, String>) {
+    let message = match &result {
+        Ok(checksums) => format!("Replay finished, {} checksums matched", checksums),
+        Err(message) => message.clone(),
+    };
+    *ecs.write_resource::<Replay>() = Replay::Finished { result };
+    ecs.write_resource::<GameLog>().entries.push(message);
+}
+
+fn write_line(replay: &mut Replay, line: &str) {
+    if let Replay::Recording { seed, file, failed } = replay {
+        if *failed {
+            return;
+        }
+        if file.is_none() {
+            match create_file(*seed) {
+                Ok(new_file) => *file = Some(new_file),
+                Err(error) => {
+                    eprintln!("Unable to create the replay file: {}", error);
+                    *failed = true;
+                    return;
+                }
+            }
+        }
+        if let Some(file) = file {
+            if let Err(error) = writeln!(file, "{}", line) {
+                eprintln!("Unable to write the replay file: {}", error);
+                *failed = true;
+            }
+        }
+    }
+}
+
+fn create_file(seed: u64) -> std::io::Result<File> {
+    fs::create_dir_all(REPLAY_DIRECTORY)?;
+    let (mut file, _path) = unique_file::create(&format!("{}/replay", REPLAY_DIRECTORY), "txt")?;
+    writeln!(file, "# Rusty Roguelike replay")?;
+    writeln!(file, "seed {}", seed)?;
+    Ok(file)
+}
+
+/// What `owner` carries, in the order the item menus list it.
+fn carried_items(ecs: &World, owner: Entity) -> Vec<Entity> {
+    let entities = ecs.entities();
+    let backpack = ecs.read_storage::<InBackpack>();
+    let names = ecs.read_storage::<Name>();
+    (&entities, &backpack, &names)
+        .join()
+        .filter(|(_entity, pack, _name)| pack.owner == owner)
+        .map(|(entity, _pack, _name)| entity)
+        .collect()
+}
+
+/// Hashes everything a replay has to reproduce. Particles are left out, as
+/// they live on frame time rather than game turns.
+pub fn world_checksum(ecs: &World) -> u64 {
+    let map = ecs.fetch::<Map>();
+    let record = ecs.fetch::<RunRecord>();
+    let player_pos = ecs.fetch::<Point>();
+    let entities = ecs.entities();
+    let particles = ecs.read_storage::<ParticleLifetime>();
+    let positions = ecs.read_storage::<Position>();
+    let names = ecs.read_storage::<Name>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let backpack = ecs.read_storage::<InBackpack>();
+    let players = ecs.read_storage::<Player>();
+    let monsters = ecs.read_storage::<Monster>();
+    let items = ecs.read_storage::<Item>();
+    let traps = ecs.read_storage::<Trap>();
+    let hidden = ecs.read_storage::<Hidden>();
+    let alerted = ecs.read_storage::<Alerted>();
+    let purses = ecs.read_storage::<Purse>();
+    let prices = ecs.read_storage::<Price>();
+
+    let mut state = format!(
+        "depth {} turns {} kills {} xp {} player {},{}\n",
+        map.depth, record.turns, record.kills, record.xp, player_pos.x, player_pos.y
+    );
+    for (tile, (revealed, decoration)) in map
+        .tiles
+        .iter()
+        .zip(map.revealed_tiles.iter().zip(map.decorations.iter()))
+    {
+        state.push_str(&format!("{:?}{}{:?};", tile, revealed, decoration));
+    }
+    state.push('\n');
+
+    for (entity, _particle) in (&entities, !&particles).join() {
+        state.push_str(&format!("{}:", entity.id()));
+        if let Some(name) = names.get(entity) {
+            state.push_str(&format!(" {}", name.name));
+        }
+        if let Some(pos) = positions.get(entity) {
+            state.push_str(&format!(" at {},{}", pos.x, pos.y));
+        }
+        if let Some(stats) = combat_stats.get(entity) {
+            state.push_str(&format!(" {:?}", stats));
+        }
+        if let Some(pack) = backpack.get(entity) {
+            state.push_str(&format!(" carried by {}", pack.owner.id()));
+        }
+        if let Some(alert) = alerted.get(entity) {
+            state.push_str(&format!(" alerted to {},{}", alert.x, alert.y));
+        }
+        if let Some(trap) = traps.get(entity) {
+            state.push_str(&format!(" {:?}", trap.kind));
+        }
+        if let Some(purse) = purses.get(entity) {
+            state.push_str(&format!(" has {} gold", purse.gold));
+        }
+        if let Some(price) = prices.get(entity) {
+            state.push_str(&format!(" costs {} gold", price.gold));
+        }
+        state.push_str(&format!(
+            " {}{}{}{}\n",
+            players.contains(entity),
+            monsters.contains(entity),
+            items.contains(entity),
+            hidden.contains(entity)
+        ));
+    }
+
+    fnv1a(state.as_bytes())
+}
+
+/// FNV-1a, chosen because its output is stable across Rust versions.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Games that have to keep playing back exactly. Changing the game rules
+    /// means recording them again.
+    const RECORDED: [&str; 1] = ["fixtures/replays/town-to-depth-1.txt"];
+
+    fn fixture(name: &str) -> String {
+        format!("{}/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn recorded_games_still_match() {
+        for name in RECORDED.iter() {
+            match check(&fixture(name)) {
+                Ok(checksums) => assert!(checksums > 0, "{} has no checksums", name),
+                Err(error) => panic!("{}: {}", name, error),
+            }
+        }
+    }
+
+    #[test]
+    fn changed_game_is_caught() {
+        let contents = fs::read_to_string(fixture(RECORDED[0])).expect("Unable to read replay");
+        let tampered = contents.replacen("descend", "wait", 1);
+        let path = std::env::temp_dir().join("roguelike-tampered-replay.txt");
+        fs::write(&path, tampered).expect("Unable to write replay");
+
+        let result = check(path.to_str().expect("Temporary path is not UTF-8"));
+        fs::remove_file(&path).expect("Unable to remove replay");
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,48 @@
+use std::env;
+
+/// Running tally of the current game, kept for the morgue file and the
+/// high-score table.
+pub struct RunRecord {
+    pub character_name: String,
+    pub turns: i32,
+    pub kills: i32,
+    pub xp: i32,
+    pub last_attacker: Option<String>,
+    pub morgue_file: Option<String>,
+    pub high_score_place: Option<usize>,
+    pub wizard: bool,
+}
+
+impl RunRecord {
+    pub fn new() -> RunRecord {
+        RunRecord {
+            character_name: character_name(),
+            turns: 0,
+            kills: 0,
+            xp: 0,
+            last_attacker: None,
+            morgue_file: None,
+            high_score_place: None,
+            wizard: false,
+        }
+    }
+
+    pub fn cause_of_death(&self) -> &str {
+        self.last_attacker.as_deref().unwrap_or("unknown causes")
+    }
+}
+
+impl Default for RunRecord {
+    fn default() -> Self {
+        RunRecord::new()
+    }
+}
+
+/// The character is named after whoever is logged in.
+fn character_name() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .ok()
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| "Adventurer".to_string())
+}
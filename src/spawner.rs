@@ -1,16 +1,31 @@
 use super::{
-    BlocksTile, CombatStats, Item, Monster, Name, Player, Position, Potion, Rect, Renderable,
-    Viewshed,
+    BlocksTile, CombatStats, Corpse, Healer, Hidden, InBackpack, Item, Key, LightSource, LootTable,
+    Map, Monster, Name, Npc, NpcBehavior, Player, Position, Potion, Price, Purse, Rect, Renderable,
+    Shopkeeper, TileType, Trap, TrapKind, Viewshed,
 };
 use crate::constants::{MAP_WIDTH, VISIBLE_TILES_RANGE};
-use rltk::{RandomNumberGenerator, RGB};
+use rltk::{DijkstraMap, RandomNumberGenerator, RGB};
 use specs::prelude::*;
 
 const MAX_MONSTERS: i32 = 4;
 const MAX_ITEMS: i32 = 2;
+const MAX_TRAPS: i32 = 2;
 const PLAYER_ORDER: i32 = 0;
 const MONSTER_ORDER: i32 = 1;
 const ITEM_ORDER: i32 = 2;
+const TRAP_ORDER: i32 = 3;
+const LIGHT_ORDER: i32 = 4;
+/// Health potions the shopkeeper has for sale.
+const SHOP_STOCK: i32 = 3;
+/// What the player sets out with. There is no way back up to the town, so
+/// this is all there is to spend.
+const STARTING_GOLD: i32 = 20;
+/// What the shopkeeper asks for a health potion.
+const POTION_PRICE: i32 = 15;
+/// One in this many rooms has a torch on its wall.
+const TORCH_ONE_IN: i32 = 2;
+/// One in this many spawn regions has glowing fungi in it.
+const FUNGUS_ONE_IN: i32 = 3;
 
 /// Spawns the player and returns his/her entity object.
 pub fn player(ecs: &mut World, x: i32, y: i32) -> Entity {
@@ -31,12 +46,19 @@ pub fn player(ecs: &mut World, x: i32, y: i32) -> Entity {
         .with(Name {
             name: "Player".to_string(),
         })
+        .with(LightSource {
+            color: RGB::from_f32(1.0, 0.85, 0.55),
+            range: 6,
+        })
         .with(CombatStats {
             max_hp: 30,
             hp: 30,
             defense: 2,
             power: 5,
         })
+        .with(Purse {
+            gold: STARTING_GOLD,
+        })
         .build()
 }
 
@@ -61,23 +83,177 @@ fn build_spawn_points_by_max_amount(ecs: &mut World, room: &Rect, max_amount: i3
 }
 
 /// Fills a room with stuff!
-pub fn spawn_room(ecs: &mut World, room: &Rect) {
+pub fn spawn_room(ecs: &mut World, map: &Map, room: &Rect) {
     let monster_spawn_points = build_spawn_points_by_max_amount(ecs, room, MAX_MONSTERS);
     let item_spawn_points = build_spawn_points_by_max_amount(ecs, room, MAX_ITEMS);
+    let trap_spawn_points = build_spawn_points_by_max_amount(ecs, room, MAX_TRAPS);
+    spawn_at_points(
+        ecs,
+        map,
+        &monster_spawn_points,
+        &item_spawn_points,
+        &trap_spawn_points,
+    );
+}
+
+/// Picks spawn points from a spawn region, as many as a room would get.
+fn region_spawn_points(ecs: &mut World, region: &[usize], max_amount: i32) -> Vec<usize> {
+    let mut spawn_points: Vec<usize> = Vec::new();
+    let mut rng = ecs.write_resource::<RandomNumberGenerator>();
+    let num_artifacts = (rng.roll_dice(1, max_amount + 2) - 3).min(region.len() as i32);
+
+    while (spawn_points.len() as i32) < num_artifacts {
+        if let Some(idx) = rng.random_slice_entry(region) {
+            if !spawn_points.contains(idx) {
+                spawn_points.push(*idx);
+            }
+        }
+    }
+    spawn_points
+}
+
+/// Fills a spawn region with stuff, the same way as a room.
+pub fn spawn_region(ecs: &mut World, map: &Map, region: &[usize]) {
+    let monster_spawn_points = region_spawn_points(ecs, region, MAX_MONSTERS);
+    let item_spawn_points = region_spawn_points(ecs, region, MAX_ITEMS);
+    let trap_spawn_points = region_spawn_points(ecs, region, MAX_TRAPS);
+    spawn_at_points(
+        ecs,
+        map,
+        &monster_spawn_points,
+        &item_spawn_points,
+        &trap_spawn_points,
+    );
+}
+
+/// Spawns everything at its point, except where the ground would drown or
+/// burn it.
+fn spawn_at_points(
+    ecs: &mut World,
+    map: &Map,
+    monster_spawn_points: &[usize],
+    item_spawn_points: &[usize],
+    trap_spawn_points: &[usize],
+) {
+    let safe = |idx: &&usize| map.tiles[**idx].is_walkable() && !map.tiles[**idx].is_hazard();
 
     // Actually spawn the monsters
-    for idx in monster_spawn_points.iter() {
+    for idx in monster_spawn_points.iter().filter(safe) {
         let x = *idx % MAP_WIDTH;
         let y = *idx / MAP_WIDTH;
         random_monster(ecs, x as i32, y as i32);
     }
 
     // Actually spawn the potions
-    for idx in item_spawn_points.iter() {
+    for idx in item_spawn_points.iter().filter(safe) {
         let x = *idx % MAP_WIDTH;
         let y = *idx / MAP_WIDTH;
         health_potion(ecs, x as i32, y as i32);
     }
+
+    // Hide the traps
+    for idx in trap_spawn_points.iter().filter(safe) {
+        let x = *idx % MAP_WIDTH;
+        let y = *idx / MAP_WIDTH;
+        random_trap(ecs, x as i32, y as i32);
+    }
+}
+
+/// Fills a freshly generated level: random stuff in every room but the
+/// start room and in every spawn region, whatever the prefabs asked for,
+/// and keys for locked doors.
+pub fn populate_level(ecs: &mut World, map: &Map, player_x: i32, player_y: i32) {
+    for room in map.rooms.iter().skip(1) {
+        spawn_room(ecs, map, room);
+    }
+    for region in map.spawn_regions.iter() {
+        spawn_region(ecs, map, region);
+    }
+    for (index, name) in map.spawns.iter() {
+        let x = *index as i32 % map.width;
+        let y = *index as i32 / map.width;
+        if name == "Trap" {
+            random_trap(ecs, x, y);
+        } else if !spawn_named_npc(ecs, name, x, y) && !spawn_named_monster(ecs, name, x, y) {
+            spawn_named_item(ecs, name, x, y);
+        }
+    }
+    spawn_door_keys(ecs, map, player_x, player_y);
+    spawn_lights(ecs, map);
+}
+
+/// Hangs torches on the walls of some rooms, and grows glowing fungi in
+/// some spawn regions.
+fn spawn_lights(ecs: &mut World, map: &Map) {
+    let rooms: &[Rect] = if map.has_rooms { &map.rooms } else { &[] };
+    for room in rooms {
+        let spot = {
+            let mut rng = ecs.write_resource::<RandomNumberGenerator>();
+            if rng.roll_dice(1, TORCH_ONE_IN) > 1 {
+                continue;
+            }
+            // Any wall tile along the top of the room
+            let x = rng.range(room.x1 + 1, room.x2 + 1);
+            let index = map.get_index_xy(x, room.y1);
+            Some(index).filter(|index| map.tiles[*index] == TileType::Wall)
+        };
+        if let Some(index) = spot {
+            torch(ecs, index as i32 % map.width, index as i32 / map.width);
+        }
+    }
+
+    for region in map.spawn_regions.iter() {
+        let spot = {
+            let mut rng = ecs.write_resource::<RandomNumberGenerator>();
+            if rng.roll_dice(1, FUNGUS_ONE_IN) > 1 {
+                continue;
+            }
+            rng.random_slice_entry(region).copied()
+        };
+        if let Some(index) = spot {
+            glowing_fungus(ecs, index as i32 % map.width, index as i32 / map.width);
+        }
+    }
+}
+
+/// Drops one key per locked door somewhere the player can reach without
+/// going through a locked door.
+fn spawn_door_keys(ecs: &mut World, map: &Map, player_x: i32, player_y: i32) {
+    let locked_doors = map
+        .tiles
+        .iter()
+        .filter(|tile| **tile == TileType::DoorLocked)
+        .count();
+    if locked_doors == 0 {
+        return;
+    }
+
+    let start = map.get_index_xy(player_x, player_y);
+    let dijkstra_map = DijkstraMap::new(map.width, map.height, &[start], map, 1024.0);
+    let mut reachable: Vec<usize> = Vec::new();
+    for room in map.rooms.iter() {
+        for y in room.y1 + 1..=room.y2 {
+            for x in room.x1 + 1..=room.x2 {
+                let index = map.get_index_xy(x, y);
+                if index != start
+                    && dijkstra_map.map[index] < f32::MAX
+                    && !map.tiles[index].is_hazard()
+                {
+                    reachable.push(index);
+                }
+            }
+        }
+    }
+
+    for _i in 0..locked_doors {
+        let spot = {
+            let mut rng = ecs.write_resource::<RandomNumberGenerator>();
+            rng.random_slice_entry(&reachable).copied()
+        };
+        if let Some(index) = spot {
+            key(ecs, index as i32 % map.width, index as i32 / map.width);
+        }
+    }
 }
 
 /// Spawns a random monster at a given location
@@ -94,14 +270,29 @@ pub fn random_monster(ecs: &mut World, x: i32, y: i32) {
 }
 
 fn orc(ecs: &mut World, x: i32, y: i32) {
-    monster(ecs, x, y, rltk::to_cp437('o'), "Orc");
+    let loot = LootTable {
+        nothing_weight: 2,
+        drops: vec![("Health Potion".to_string(), 1)],
+    };
+    monster(ecs, x, y, rltk::to_cp437('o'), "Orc", loot);
 }
 
 fn goblin(ecs: &mut World, x: i32, y: i32) {
-    monster(ecs, x, y, rltk::to_cp437('g'), "Goblin");
+    let loot = LootTable {
+        nothing_weight: 3,
+        drops: vec![("Health Potion".to_string(), 1)],
+    };
+    monster(ecs, x, y, rltk::to_cp437('g'), "Goblin", loot);
 }
 
-fn monster<S: ToString>(ecs: &mut World, x: i32, y: i32, glyph: rltk::FontCharType, name: S) {
+fn monster<S: ToString>(
+    ecs: &mut World,
+    x: i32,
+    y: i32,
+    glyph: rltk::FontCharType,
+    name: S,
+    loot: LootTable,
+) {
     ecs.create_entity()
         .with(Position { x, y })
         .with(Renderable {
@@ -126,10 +317,151 @@ fn monster<S: ToString>(ecs: &mut World, x: i32, y: i32, glyph: rltk::FontCharTy
             defense: 1,
             power: 4,
         })
+        .with(loot)
         .build();
 }
 
-fn health_potion(ecs: &mut World, x: i32, y: i32) {
+/// Spawns a monster by its name. Returns false if there is no such monster.
+pub fn spawn_named_monster(ecs: &mut World, name: &str, x: i32, y: i32) -> bool {
+    match name {
+        "Orc" => orc(ecs, x, y),
+        "Goblin" => goblin(ecs, x, y),
+        _ => return false,
+    }
+    true
+}
+
+/// Spawns a friendly NPC by its name. Returns false if there is no such NPC.
+pub fn spawn_named_npc(ecs: &mut World, name: &str, x: i32, y: i32) -> bool {
+    match name {
+        "Shopkeeper" => shopkeeper(ecs, x, y),
+        "Healer" => healer(ecs, x, y),
+        "Villager" => {
+            npc(
+                ecs,
+                x,
+                y,
+                RGB::named(rltk::LIGHT_GREEN),
+                "Villager",
+                NpcBehavior::Wander,
+            );
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Stays behind the counter, with a few potions to spare.
+fn shopkeeper(ecs: &mut World, x: i32, y: i32) {
+    let shopkeeper = npc(
+        ecs,
+        x,
+        y,
+        RGB::named(rltk::ORANGE),
+        "Shopkeeper",
+        NpcBehavior::StayPut,
+    );
+    ecs.write_storage::<Shopkeeper>()
+        .insert(shopkeeper, Shopkeeper {})
+        .expect("Unable to insert shopkeeper");
+    for _i in 0..SHOP_STOCK {
+        let potion = health_potion(ecs, x, y);
+        ecs.write_storage::<Position>().remove(potion);
+        ecs.write_storage::<InBackpack>()
+            .insert(potion, InBackpack { owner: shopkeeper })
+            .expect("Unable to insert backpack entry");
+        ecs.write_storage::<Price>()
+            .insert(potion, Price { gold: POTION_PRICE })
+            .expect("Unable to insert price");
+    }
+}
+
+fn healer(ecs: &mut World, x: i32, y: i32) {
+    let healer = npc(
+        ecs,
+        x,
+        y,
+        RGB::named(rltk::WHITE),
+        "Healer",
+        NpcBehavior::StayPut,
+    );
+    ecs.write_storage::<Healer>()
+        .insert(healer, Healer {})
+        .expect("Unable to insert healer");
+}
+
+fn npc<S: ToString>(
+    ecs: &mut World,
+    x: i32,
+    y: i32,
+    fg: RGB,
+    name: S,
+    behavior: NpcBehavior,
+) -> Entity {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('@'),
+            fg,
+            bg: RGB::named(rltk::BLACK),
+            render_order: MONSTER_ORDER,
+        })
+        .with(Name {
+            name: name.to_string(),
+        })
+        .with(Npc { behavior })
+        .with(BlocksTile {})
+        .build()
+}
+
+/// Spawns an item by its name. Returns `None` if there is no such item.
+pub fn spawn_named_item(ecs: &mut World, name: &str, x: i32, y: i32) -> Option<Entity> {
+    match name {
+        "Health Potion" => Some(health_potion(ecs, x, y)),
+        "Key" => Some(key(ecs, x, y)),
+        _ => None,
+    }
+}
+
+/// Picks an item name from a loot table, or `None` when nothing drops.
+pub fn roll_loot(ecs: &mut World, loot: &LootTable) -> Option<String> {
+    let total = loot.nothing_weight + loot.drops.iter().map(|(_, weight)| weight).sum::<i32>();
+    if total < 1 {
+        return None;
+    }
+
+    let mut roll = {
+        let mut rng = ecs.write_resource::<RandomNumberGenerator>();
+        rng.roll_dice(1, total) - 1
+    };
+    for (name, weight) in loot.drops.iter() {
+        if roll < *weight {
+            return Some(name.clone());
+        }
+        roll -= weight;
+    }
+    None
+}
+
+/// Leaves the body of a dead creature on the floor.
+pub fn corpse(ecs: &mut World, x: i32, y: i32, name: &str) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('%'),
+            fg: RGB::from_f32(0.6, 0., 0.),
+            bg: RGB::named(rltk::BLACK),
+            render_order: ITEM_ORDER,
+        })
+        .with(Name {
+            name: format!("{} corpse", name),
+        })
+        .with(Item {})
+        .with(Corpse {})
+        .build();
+}
+
+fn health_potion(ecs: &mut World, x: i32, y: i32) -> Entity {
     ecs.create_entity()
         .with(Position { x, y })
         .with(Renderable {
@@ -143,5 +475,122 @@ fn health_potion(ecs: &mut World, x: i32, y: i32) {
         })
         .with(Item {})
         .with(Potion { heal_amount: 8 })
+        .build()
+}
+
+fn key(ecs: &mut World, x: i32, y: i32) -> Entity {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('-'),
+            fg: RGB::named(rltk::GOLD),
+            bg: RGB::named(rltk::BLACK),
+            render_order: ITEM_ORDER,
+        })
+        .with(Name {
+            name: "Key".to_string(),
+        })
+        .with(Item {})
+        .with(Key {})
+        .build()
+}
+
+fn torch(ecs: &mut World, x: i32, y: i32) {
+    light(
+        ecs,
+        x,
+        y,
+        rltk::to_cp437('*'),
+        "Torch",
+        RGB::from_f32(1.0, 0.6, 0.2),
+        8,
+    );
+}
+
+fn glowing_fungus(ecs: &mut World, x: i32, y: i32) {
+    light(
+        ecs,
+        x,
+        y,
+        rltk::to_cp437('"'),
+        "Glowing Fungus",
+        RGB::from_f32(0.3, 0.9, 0.4),
+        4,
+    );
+}
+
+fn light<S: ToString>(
+    ecs: &mut World,
+    x: i32,
+    y: i32,
+    glyph: rltk::FontCharType,
+    name: S,
+    color: RGB,
+    range: i32,
+) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph,
+            fg: color,
+            bg: RGB::named(rltk::BLACK),
+            render_order: LIGHT_ORDER,
+        })
+        .with(Name {
+            name: name.to_string(),
+        })
+        .with(LightSource { color, range })
+        .build();
+}
+
+/// Spawns a random hidden trap at a given location
+pub fn random_trap(ecs: &mut World, x: i32, y: i32) {
+    let roll: i32;
+    {
+        let mut rng = ecs.write_resource::<RandomNumberGenerator>();
+        roll = rng.roll_dice(1, 3);
+    }
+    match roll {
+        1 => trap(
+            ecs,
+            x,
+            y,
+            RGB::named(rltk::RED),
+            "Spike Trap",
+            TrapKind::Spike { damage: 6 },
+        ),
+        2 => trap(
+            ecs,
+            x,
+            y,
+            RGB::named(rltk::MAGENTA),
+            "Teleport Trap",
+            TrapKind::Teleport,
+        ),
+        _ => trap(
+            ecs,
+            x,
+            y,
+            RGB::named(rltk::YELLOW),
+            "Alarm Trap",
+            TrapKind::Alarm,
+        ),
+    }
+}
+
+fn trap<S: ToString>(ecs: &mut World, x: i32, y: i32, fg: RGB, name: S, kind: TrapKind) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('^'),
+            fg,
+            bg: RGB::named(rltk::BLACK),
+            render_order: TRAP_ORDER,
+        })
+        .with(Name {
+            name: name.to_string(),
+        })
+        .with(Trap { kind })
+        .with(Hidden {})
         .build();
 }
@@ -0,0 +1,95 @@
+use super::{Map, TileType};
+use rltk::RandomNumberGenerator;
+
+/// Steps taken by the random walk that lays down each patch.
+const POOL_STEPS: i32 = 60;
+const RUBBLE_STEPS: i32 = 25;
+const LAVA_STEPS: i32 = 30;
+/// Lava only shows up this deep or deeper.
+const LAVA_MIN_DEPTH: i32 = 2;
+/// Nothing is laid down this many tiles or fewer from the player start.
+const START_CLEARANCE: i32 = 3;
+
+/// Lays pools of water, heaps of rubble and, deeper down, flows of lava
+/// over the floor of a finished level. Doors, stairs and prefab spawns are
+/// left alone. Pools are only deep where water surrounds them, so there is
+/// always a shallow way round their edge.
+pub fn scatter(map: &mut Map, rng: &mut RandomNumberGenerator) {
+    for _pool in 0..rng.roll_dice(1, 3) - 1 {
+        splash(map, rng, TileType::ShallowWater, POOL_STEPS);
+    }
+    deepen_pools(map);
+    for _heap in 0..rng.roll_dice(1, 3) - 1 {
+        splash(map, rng, TileType::Rubble, RUBBLE_STEPS);
+    }
+    if map.depth >= LAVA_MIN_DEPTH {
+        for _flow in 0..rng.roll_dice(1, 2) - 1 {
+            splash(map, rng, TileType::Lava, LAVA_STEPS);
+        }
+    }
+    map.take_snapshot();
+}
+
+/// Walks at random from a random floor tile, turning the floor it crosses
+/// into `tile`.
+fn splash(map: &mut Map, rng: &mut RandomNumberGenerator, tile: TileType, steps: i32) {
+    let floor: Vec<usize> = (0..map.tiles.len())
+        .filter(|index| can_cover(map, *index))
+        .collect();
+    let start = match rng.random_slice_entry(&floor) {
+        Some(start) => *start,
+        None => return,
+    };
+
+    let (mut x, mut y) = (start as i32 % map.width, start as i32 / map.width);
+    for _step in 0..steps {
+        let index = map.get_index_xy(x, y);
+        if can_cover(map, index) {
+            map.tiles[index] = tile;
+        }
+        match rng.roll_dice(1, 4) {
+            1 => x -= 1,
+            2 => x += 1,
+            3 => y -= 1,
+            _ => y += 1,
+        }
+        x = x.clamp(1, map.width - 2);
+        y = y.clamp(1, map.height - 2);
+    }
+}
+
+fn can_cover(map: &Map, index: usize) -> bool {
+    if map.tiles[index] != TileType::Floor
+        || map.spawns.iter().any(|(spawn, _name)| *spawn == index)
+    {
+        return false;
+    }
+    let (start_x, start_y) = map.rooms[0].center();
+    let (x, y) = (index as i32 % map.width, index as i32 / map.width);
+    (x - start_x).abs().max((y - start_y).abs()) > START_CLEARANCE
+}
+
+/// Shallow water with nothing but water all around it becomes deep.
+fn deepen_pools(map: &mut Map) {
+    let deep: Vec<usize> = (0..map.tiles.len())
+        .filter(|index| {
+            let (x, y) = (*index as i32 % map.width, *index as i32 / map.width);
+            map.tiles[*index] == TileType::ShallowWater
+                && x > 0
+                && x < map.width - 1
+                && y > 0
+                && y < map.height - 1
+                && (-1..=1).all(|dy| {
+                    (-1..=1).all(|dx| {
+                        matches!(
+                            map.tiles[map.get_index_xy(x + dx, y + dy)],
+                            TileType::ShallowWater | TileType::DeepWater
+                        )
+                    })
+                })
+        })
+        .collect();
+    for index in deep {
+        map.tiles[index] = TileType::DeepWater;
+    }
+}
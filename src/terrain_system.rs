@@ -0,0 +1,105 @@
+use super::{
+    Alerted, CombatStats, EntityMoved, GameLog, Map, Monster, Position, RunState, SufferDamage,
+    TileType, Wading,
+};
+use crate::run_record::RunRecord;
+use rltk::Point;
+use specs::prelude::*;
+
+const LAVA_DAMAGE: i32 = 4;
+/// Monsters this close hear the player splash through shallow water.
+const SPLASH_RADIUS: f32 = 8.0;
+
+pub struct TerrainSystem {}
+
+impl<'a> System<'a> for TerrainSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        ReadExpect<'a, Map>,
+        ReadExpect<'a, Entity>,
+        ReadExpect<'a, RunState>,
+        WriteExpect<'a, GameLog>,
+        WriteExpect<'a, RunRecord>,
+        Entities<'a>,
+        ReadStorage<'a, EntityMoved>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, CombatStats>,
+        ReadStorage<'a, Monster>,
+        WriteStorage<'a, Wading>,
+        WriteStorage<'a, Alerted>,
+        WriteStorage<'a, SufferDamage>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            map,
+            player_entity,
+            runstate,
+            mut log,
+            mut record,
+            entities,
+            entity_moved,
+            positions,
+            combat_stats,
+            monsters,
+            mut wading,
+            mut alerted,
+            mut inflict_damage,
+        ) = data;
+
+        let mut splash: Option<Point> = None;
+        for (entity, _moved, pos) in (&entities, &entity_moved, &positions).join() {
+            let tile = map.tiles[map.get_index_xy(pos.x, pos.y)];
+            if tile.is_slow() {
+                wading
+                    .insert(entity, Wading {})
+                    .expect("Unable to insert wading");
+            }
+            if entity == *player_entity && tile == TileType::ShallowWater {
+                splash = Some(Point::new(pos.x, pos.y));
+            }
+        }
+
+        // There is no sneaking through water
+        if let Some(splash) = splash {
+            for (entity, _monster, pos) in (&entities, &monsters, &positions).join() {
+                let distance =
+                    rltk::DistanceAlg::Pythagoras.distance2d(Point::new(pos.x, pos.y), splash);
+                if distance <= SPLASH_RADIUS {
+                    alerted
+                        .insert(
+                            entity,
+                            Alerted {
+                                x: splash.x,
+                                y: splash.y,
+                            },
+                        )
+                        .expect("Unable to insert alert");
+                }
+            }
+        }
+
+        // Lava burns once a turn, whatever stands in it
+        if *runstate == RunState::MonsterTurn {
+            for (entity, pos, _stats) in (&entities, &positions, &combat_stats).join() {
+                if !map.tiles[map.get_index_xy(pos.x, pos.y)].is_hazard() {
+                    continue;
+                }
+                SufferDamage::new_damage(&mut inflict_damage, entity, LAVA_DAMAGE, false);
+                if entity == *player_entity {
+                    log.entries.push("The lava burns you!".to_string());
+                    record.last_attacker = Some("lava".to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Takes the player's `Wading` away, and says whether there was one: the
+/// monsters then get another turn while the player climbs clear.
+pub fn player_slowed(ecs: &mut World) -> bool {
+    let player_entity = *ecs.fetch::<Entity>();
+    ecs.write_storage::<Wading>()
+        .remove(player_entity)
+        .is_some()
+}
@@ -0,0 +1,142 @@
+use super::{
+    Alerted, Decoration, EntityMoved, GameLog, Hidden, Map, Monster, Name, Position, SufferDamage,
+    TileType, Trap, TrapKind, Viewshed,
+};
+use crate::run_record::RunRecord;
+use rltk::{Point, RandomNumberGenerator};
+use specs::prelude::*;
+
+const ALARM_RADIUS: f32 = 20.0;
+
+pub struct TriggerSystem {}
+
+impl<'a> System<'a> for TriggerSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        WriteExpect<'a, Map>,
+        ReadExpect<'a, Entity>,
+        WriteExpect<'a, Point>,
+        WriteExpect<'a, GameLog>,
+        WriteExpect<'a, RandomNumberGenerator>,
+        Entities<'a>,
+        WriteStorage<'a, EntityMoved>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Viewshed>,
+        ReadStorage<'a, Trap>,
+        WriteStorage<'a, Hidden>,
+        ReadStorage<'a, Name>,
+        ReadStorage<'a, Monster>,
+        WriteStorage<'a, Alerted>,
+        WriteStorage<'a, SufferDamage>,
+        WriteExpect<'a, RunRecord>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            mut map,
+            player_entity,
+            mut player_pos,
+            mut log,
+            mut rng,
+            entities,
+            mut entity_moved,
+            mut positions,
+            mut viewsheds,
+            traps,
+            mut hidden,
+            names,
+            monsters,
+            mut alerted,
+            mut inflict_damage,
+            mut record,
+        ) = data;
+
+        let mut teleported: Vec<Entity> = Vec::new();
+        let mut scorched: Vec<usize> = Vec::new();
+        let mut alarms: Vec<Point> = Vec::new();
+
+        for (entity, _moved, pos) in (&entities, &entity_moved, &positions).join() {
+            let index = map.get_index_xy(pos.x, pos.y);
+            for trap_entity in map.tile_content[index].iter() {
+                let trap = match traps.get(*trap_entity) {
+                    Some(trap) => trap,
+                    None => continue,
+                };
+
+                if entity == *player_entity || map.visible_tiles[index] {
+                    hidden.remove(*trap_entity);
+                    let victim_name = names.get(entity).map_or("Something", |n| &n.name);
+                    let trap_name = names.get(*trap_entity).map_or("trap", |n| &n.name);
+                    log.entries
+                        .push(format!("{} triggers a {}!", victim_name, trap_name));
+                }
+
+                match trap.kind {
+                    TrapKind::Spike { damage } => {
+                        SufferDamage::new_damage(&mut inflict_damage, entity, damage, false);
+                        if entity == *player_entity {
+                            record.last_attacker = names.get(*trap_entity).map(|n| n.name.clone());
+                        }
+                    }
+                    TrapKind::Teleport => {
+                        teleported.push(entity);
+                        scorched.push(index);
+                    }
+                    TrapKind::Alarm => alarms.push(Point::new(pos.x, pos.y)),
+                }
+            }
+        }
+        entity_moved.clear();
+
+        // The teleport flash burns the floor it leaves from
+        for index in scorched {
+            map.add_decoration(index, Decoration::ScorchMark);
+        }
+
+        for entity in teleported {
+            if let Some(destination) = random_open_tile(&map, &mut rng) {
+                if let Some(pos) = positions.get_mut(entity) {
+                    pos.x = destination.x;
+                    pos.y = destination.y;
+                }
+                if let Some(viewshed) = viewsheds.get_mut(entity) {
+                    viewshed.dirty = true;
+                }
+                if entity == *player_entity {
+                    *player_pos = destination;
+                }
+            }
+        }
+
+        for alarm in alarms {
+            log.entries.push("An alarm rings out!".to_string());
+            for (entity, _monster, pos) in (&entities, &monsters, &positions).join() {
+                let distance =
+                    rltk::DistanceAlg::Pythagoras.distance2d(Point::new(pos.x, pos.y), alarm);
+                if distance <= ALARM_RADIUS {
+                    alerted
+                        .insert(
+                            entity,
+                            Alerted {
+                                x: alarm.x,
+                                y: alarm.y,
+                            },
+                        )
+                        .expect("Unable to insert alert");
+                }
+            }
+        }
+    }
+}
+
+fn random_open_tile(map: &Map, rng: &mut RandomNumberGenerator) -> Option<Point> {
+    for _attempt in 0..1000 {
+        let x = rng.range(1, map.width - 1);
+        let y = rng.range(1, map.height - 1);
+        let index = map.get_index_xy(x, y);
+        if map.tiles[index] == TileType::Floor && !map.blocked[index] {
+            return Some(Point::new(x, y));
+        }
+    }
+    None
+}
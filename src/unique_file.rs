@@ -0,0 +1,27 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Creates a new file named `{prefix}-{timestamp}.{extension}` for writing.
+/// When a file made in the same second already has that name, a counter is
+/// added instead of overwriting it. Returns the file and its path.
+pub fn create(prefix: &str, extension: &str) -> io::Result<(File, String)> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+
+    let mut counter = 0;
+    loop {
+        let path = if counter == 0 {
+            format!("{}-{}.{}", prefix, timestamp, extension)
+        } else {
+            format!("{}-{}-{}.{}", prefix, timestamp, counter, extension)
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => counter += 1,
+            Err(error) => return Err(error),
+        }
+    }
+}
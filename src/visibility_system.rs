@@ -1,20 +1,42 @@
-use super::{Map, Player, Position, Viewshed};
-use rltk::{field_of_view, Point};
+use super::{Corpse, GameLog, Hidden, Map, Name, Player, Position, Trap, Viewshed};
+use rltk::{field_of_view, Point, RandomNumberGenerator};
 use specs::prelude::*;
 
+/// A hidden trap in view is spotted when a d20 roll reaches this.
+const TRAP_SPOT_DIFFICULTY: i32 = 16;
+
 pub struct VisibilitySystem {}
 
 impl<'a> System<'a> for VisibilitySystem {
+    #[allow(clippy::type_complexity)]
     type SystemData = (
         Entities<'a>,
         WriteExpect<'a, Map>,
         WriteStorage<'a, Viewshed>,
         WriteStorage<'a, Position>,
         ReadStorage<'a, Player>,
+        WriteStorage<'a, Hidden>,
+        ReadStorage<'a, Trap>,
+        ReadStorage<'a, Name>,
+        ReadStorage<'a, Corpse>,
+        WriteExpect<'a, RandomNumberGenerator>,
+        WriteExpect<'a, GameLog>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (entities, mut map, mut viewshed, pos, player) = data;
+        let (
+            entities,
+            mut map,
+            mut viewshed,
+            pos,
+            player,
+            mut hidden,
+            traps,
+            names,
+            corpses,
+            mut rng,
+            mut log,
+        ) = data;
 
         for (entity, viewshed, pos) in (&entities, &mut viewshed, &pos).join() {
             if viewshed.dirty {
@@ -36,6 +58,26 @@ impl<'a> System<'a> for VisibilitySystem {
                         let index = map.get_index_xy(visibility.x, visibility.y);
                         map.revealed_tiles[index] = true;
                         map.visible_tiles[index] = true;
+
+                        if !map.tile_content[index]
+                            .iter()
+                            .any(|entity| corpses.get(*entity).is_some())
+                        {
+                            map.forget_corpse(index);
+                        }
+
+                        // Perception check for anything hidden in view
+                        for entity in map.tile_content[index].iter() {
+                            if hidden.get(*entity).is_some()
+                                && traps.get(*entity).is_some()
+                                && rng.roll_dice(1, 20) >= TRAP_SPOT_DIFFICULTY
+                            {
+                                if let Some(name) = names.get(*entity) {
+                                    log.entries.push(format!("You spot a {}.", &name.name));
+                                }
+                                hidden.remove(*entity);
+                            }
+                        }
                     }
                 }
             }
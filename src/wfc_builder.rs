@@ -0,0 +1,294 @@
+use super::{cave_builder, Map, TileType};
+use rltk::RandomNumberGenerator;
+
+/// Width and height of the chunks the source level is cut into.
+const CHUNK_SIZE: i32 = 8;
+/// Contradictions are solved by starting over, this many times at most.
+const MAX_ATTEMPTS: i32 = 10;
+
+/// Directions between neighbouring chunks, in the order edges are kept.
+const NORTH: usize = 0;
+const EAST: usize = 1;
+const SOUTH: usize = 2;
+const WEST: usize = 3;
+
+/// A piece of the source level, with how often it was seen and which
+/// chunks may sit next to it on each side.
+struct Chunk {
+    tiles: Vec<TileType>,
+    frequency: i32,
+    compatible: [Vec<bool>; 4],
+}
+
+/// Builds a level by wave function collapse over chunks of another
+/// generated level. Falls back to the source level itself when every
+/// attempt runs into a contradiction.
+pub fn build(depth: i32, rng: &mut RandomNumberGenerator) -> Map {
+    let source = if rng.roll_dice(1, 2) == 1 {
+        cave_builder::build(depth, rng)
+    } else {
+        Map::new_rooms_and_corridors(depth, rng)
+    };
+    build_from(depth, &source, rng).unwrap_or(source)
+}
+
+/// Builds a level from the chunks of `source`, which may be any level,
+/// such as one loaded from a map file, and culls what cannot be reached.
+/// Returns `None` if every attempt runs into a contradiction, or the chunks
+/// leave no floor to start on.
+pub fn build_from(depth: i32, source: &Map, rng: &mut RandomNumberGenerator) -> Option<Map> {
+    let chunks = learn_chunks(source);
+    let mut map = Map::new(depth);
+    let columns = (map.width / CHUNK_SIZE) as usize;
+    let rows = (map.height / CHUNK_SIZE) as usize;
+
+    for _attempt in 0..MAX_ATTEMPTS {
+        // Only the attempt that works is worth showing
+        map.tiles = vec![TileType::Wall; map.tiles.len()];
+        map.history.clear();
+        if collapse(&mut map, &chunks, columns, rows, rng) {
+            // Keep the level closed in, whatever the chunks at the edge hold
+            for x in 0..map.width {
+                for y in [0, map.height - 1] {
+                    let index = map.get_index_xy(x, y);
+                    map.tiles[index] = TileType::Wall;
+                }
+            }
+            for y in 0..map.height {
+                for x in [0, map.width - 1] {
+                    let index = map.get_index_xy(x, y);
+                    map.tiles[index] = TileType::Wall;
+                }
+            }
+            cave_builder::finish_caverns(&mut map, rng);
+            if map.cull_unreachable() == 0 {
+                return None;
+            }
+            return Some(map);
+        }
+    }
+    None
+}
+
+/// Cuts the source into chunks, keeping only walls and floor, and works
+/// out which pairs of chunks fit together. Two edges fit when they are
+/// both solid wall, or when at least one tile is open on both, so a way
+/// through one chunk carries on into the next.
+fn learn_chunks(source: &Map) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    for chunk_y in 0..source.height / CHUNK_SIZE {
+        for chunk_x in 0..source.width / CHUNK_SIZE {
+            let mut tiles = Vec::new();
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let index =
+                        source.get_index_xy(chunk_x * CHUNK_SIZE + x, chunk_y * CHUNK_SIZE + y);
+                    tiles.push(if source.tiles[index] == TileType::Wall {
+                        TileType::Wall
+                    } else {
+                        TileType::Floor
+                    });
+                }
+            }
+            match chunks.iter_mut().find(|chunk| chunk.tiles == tiles) {
+                Some(chunk) => chunk.frequency += 1,
+                None => chunks.push(Chunk {
+                    tiles,
+                    frequency: 1,
+                    compatible: Default::default(),
+                }),
+            }
+        }
+    }
+
+    let edges: Vec<[Vec<bool>; 4]> = chunks
+        .iter()
+        .map(|chunk| open_edges(&chunk.tiles))
+        .collect();
+    for (chunk, chunk_edges) in chunks.iter_mut().zip(edges.iter()) {
+        for direction in [NORTH, EAST, SOUTH, WEST] {
+            let facing = (direction + 2) % 4;
+            chunk.compatible[direction] = edges
+                .iter()
+                .map(|other_edges| {
+                    let ours = &chunk_edges[direction];
+                    let theirs = &other_edges[facing];
+                    let both_solid = !ours.contains(&true) && !theirs.contains(&true);
+                    let shared_opening = ours.iter().zip(theirs.iter()).any(|(a, b)| *a && *b);
+                    both_solid || shared_opening
+                })
+                .collect();
+        }
+    }
+    chunks
+}
+
+/// Which tiles along each edge of a chunk are open.
+fn open_edges(tiles: &[TileType]) -> [Vec<bool>; 4] {
+    let open = |x: i32, y: i32| tiles[(y * CHUNK_SIZE + x) as usize] != TileType::Wall;
+    let last = CHUNK_SIZE - 1;
+    [
+        (0..CHUNK_SIZE).map(|x| open(x, 0)).collect(),
+        (0..CHUNK_SIZE).map(|y| open(last, y)).collect(),
+        (0..CHUNK_SIZE).map(|x| open(x, last)).collect(),
+        (0..CHUNK_SIZE).map(|y| open(0, y)).collect(),
+    ]
+}
+
+/// Fills the grid of chunks: the cell with the fewest options left gets one
+/// of them, picked by how common it was in the source, and its neighbours
+/// lose the options that no longer fit. Returns false on a contradiction.
+fn collapse(
+    map: &mut Map,
+    chunks: &[Chunk],
+    columns: usize,
+    rows: usize,
+    rng: &mut RandomNumberGenerator,
+) -> bool {
+    let mut options: Vec<Vec<bool>> = vec![vec![true; chunks.len()]; columns * rows];
+    let mut placed: Vec<bool> = vec![false; columns * rows];
+
+    loop {
+        let counts: Vec<usize> = options
+            .iter()
+            .map(|cell| cell.iter().filter(|open| **open).count())
+            .collect();
+        if counts.contains(&0) {
+            return false;
+        }
+        let fewest = (0..options.len())
+            .filter(|cell| !placed[*cell])
+            .map(|cell| counts[cell])
+            .min();
+        let fewest = match fewest {
+            Some(fewest) => fewest,
+            None => return true,
+        };
+        let candidates: Vec<usize> = (0..options.len())
+            .filter(|cell| !placed[*cell] && counts[*cell] == fewest)
+            .collect();
+        let cell = *rng
+            .random_slice_entry(&candidates)
+            .expect("There is always an unplaced cell here");
+
+        let chosen = pick_weighted(&options[cell], chunks, rng);
+        for (index, open) in options[cell].iter_mut().enumerate() {
+            *open = index == chosen;
+        }
+        placed[cell] = true;
+        write_chunk(map, &chunks[chosen], cell % columns, cell / columns);
+        map.take_snapshot();
+
+        propagate(&mut options, chunks, columns, rows, cell);
+    }
+}
+
+fn pick_weighted(options: &[bool], chunks: &[Chunk], rng: &mut RandomNumberGenerator) -> usize {
+    let total: i32 = (0..chunks.len())
+        .filter(|index| options[*index])
+        .map(|index| chunks[index].frequency)
+        .sum();
+    let mut roll = rng.roll_dice(1, total);
+    for (index, chunk) in chunks.iter().enumerate() {
+        if !options[index] {
+            continue;
+        }
+        roll -= chunk.frequency;
+        if roll <= 0 {
+            return index;
+        }
+    }
+    unreachable!("The roll is never more than the total weight")
+}
+
+/// Spreads the change in `start` out to its neighbours, and theirs, until
+/// nothing else can be ruled out.
+fn propagate(
+    options: &mut [Vec<bool>],
+    chunks: &[Chunk],
+    columns: usize,
+    rows: usize,
+    start: usize,
+) {
+    let mut changed = vec![start];
+    while let Some(cell) = changed.pop() {
+        let (x, y) = (cell % columns, cell / columns);
+        let neighbours = [
+            (NORTH, y > 0, cell.wrapping_sub(columns)),
+            (EAST, x + 1 < columns, cell + 1),
+            (SOUTH, y + 1 < rows, cell + columns),
+            (WEST, x > 0, cell.wrapping_sub(1)),
+        ];
+        for (direction, exists, neighbour) in neighbours {
+            if !exists {
+                continue;
+            }
+            let mut narrowed = false;
+            for other in 0..chunks.len() {
+                if !options[neighbour][other] {
+                    continue;
+                }
+                let fits = (0..chunks.len())
+                    .any(|ours| options[cell][ours] && chunks[ours].compatible[direction][other]);
+                if !fits {
+                    options[neighbour][other] = false;
+                    narrowed = true;
+                }
+            }
+            if narrowed {
+                changed.push(neighbour);
+            }
+        }
+    }
+}
+
+fn write_chunk(map: &mut Map, chunk: &Chunk, column: usize, row: usize) {
+    for y in 0..CHUNK_SIZE {
+        for x in 0..CHUNK_SIZE {
+            let index =
+                map.get_index_xy(column as i32 * CHUNK_SIZE + x, row as i32 * CHUNK_SIZE + y);
+            map.tiles[index] = chunk.tiles[(y * CHUNK_SIZE + x) as usize];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_check::playable_problems;
+
+    #[test]
+    fn seeded_wfc_levels_are_playable() {
+        for seed in 0..5 {
+            let mut rng = RandomNumberGenerator::seeded(seed);
+            let mut map = build(1, &mut rng);
+            let problems = playable_problems(&mut map);
+            assert!(
+                problems.is_empty(),
+                "seed {}: {}",
+                seed,
+                problems.join("; ")
+            );
+        }
+    }
+
+    #[test]
+    fn seeded_wfc_levels_from_rooms_are_playable() {
+        let mut built = 0;
+        for seed in 0..5 {
+            let mut rng = RandomNumberGenerator::seeded(seed);
+            let source = Map::new_rooms_and_corridors(1, &mut rng);
+            if let Some(mut map) = build_from(1, &source, &mut rng) {
+                let problems = playable_problems(&mut map);
+                assert!(
+                    problems.is_empty(),
+                    "seed {}: {}",
+                    seed,
+                    problems.join("; ")
+                );
+                built += 1;
+            }
+        }
+        assert!(built > 0, "every seed ran into a contradiction");
+    }
+}
@@ -0,0 +1,361 @@
+use super::{
+    map_file, map_generation, map_indexing_system::MapIndexingSystem, replay,
+    run_record::RunRecord, spawner, wfc_builder, InBackpack, Map, Position, RunState, State,
+    Swimmer, Viewshed,
+};
+use rltk::{Point, Rltk, VirtualKeyCode};
+use specs::prelude::*;
+
+/// How many lines of console output are kept.
+const MAX_OUTPUT_LINES: usize = 4;
+
+/// The wizard console, for testing content without playing until it shows
+/// up. Only available when built with the `wizard` feature or started with
+/// `--wizard`.
+pub struct Wizard {
+    pub enabled: bool,
+    pub god_mode: bool,
+    pub input: String,
+    pub output: Vec<String>,
+}
+
+impl Wizard {
+    pub fn new(enabled: bool) -> Wizard {
+        Wizard {
+            enabled,
+            god_mode: false,
+            input: String::new(),
+            output: Vec::new(),
+        }
+    }
+}
+
+/// Handles a key press while the console is open.
+pub fn console_input(gs: &mut State, ctx: &mut Rltk) -> RunState {
+    let key = match ctx.key {
+        None => return RunState::WizardConsole,
+        Some(key) => key,
+    };
+
+    match key {
+        VirtualKeyCode::Escape | VirtualKeyCode::Grave => return RunState::AwaitingInput,
+        VirtualKeyCode::Back => {
+            gs.ecs.write_resource::<Wizard>().input.pop();
+        }
+        VirtualKeyCode::Return => {
+            let line = std::mem::take(&mut gs.ecs.write_resource::<Wizard>().input);
+            if !line.trim().is_empty() {
+                print(&gs.ecs, format!("> {}", line));
+                let depth = gs.ecs.fetch::<Map>().depth;
+                let result = run_command(gs, &line);
+                print(&gs.ecs, result);
+                if gs.ecs.fetch::<Map>().depth != depth {
+                    return map_generation::start(&mut gs.ecs, RunState::WizardConsole);
+                }
+            }
+        }
+        _ => {
+            if let Some(c) = key_to_char(key, ctx.shift) {
+                gs.ecs.write_resource::<Wizard>().input.push(c);
+            }
+        }
+    }
+    RunState::WizardConsole
+}
+
+fn print(ecs: &World, line: String) {
+    let mut wizard = ecs.write_resource::<Wizard>();
+    wizard.output.push(line);
+    let excess = wizard.output.len().saturating_sub(MAX_OUTPUT_LINES);
+    wizard.output.drain(..excess);
+}
+
+fn key_to_char(key: VirtualKeyCode, shift: bool) -> Option<char> {
+    use VirtualKeyCode::*;
+    let c = match key {
+        A => 'a',
+        B => 'b',
+        C => 'c',
+        D => 'd',
+        E => 'e',
+        F => 'f',
+        G => 'g',
+        H => 'h',
+        I => 'i',
+        J => 'j',
+        K => 'k',
+        L => 'l',
+        M => 'm',
+        N => 'n',
+        O => 'o',
+        P => 'p',
+        Q => 'q',
+        R => 'r',
+        S => 's',
+        T => 't',
+        U => 'u',
+        V => 'v',
+        W => 'w',
+        X => 'x',
+        Y => 'y',
+        Z => 'z',
+        Key0 | Numpad0 => '0',
+        Key1 | Numpad1 => '1',
+        Key2 | Numpad2 => '2',
+        Key3 | Numpad3 => '3',
+        Key4 | Numpad4 => '4',
+        Key5 | Numpad5 => '5',
+        Key6 | Numpad6 => '6',
+        Key7 | Numpad7 => '7',
+        Key8 | Numpad8 => '8',
+        Key9 | Numpad9 => '9',
+        Minus if shift => '_',
+        Minus | NumpadSubtract => '-',
+        Period | NumpadDecimal => '.',
+        Slash | NumpadDivide => '/',
+        Backslash => '\\',
+        Space => ' ',
+        Apostrophe if shift => '"',
+        Apostrophe => '\'',
+        _ => return None,
+    };
+    Some(if shift { c.to_ascii_uppercase() } else { c })
+}
+
+/// Splits a command line into words, keeping "quoted names" together.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' if !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            _ => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// "health potion" becomes "Health Potion", the way spawner names things.
+fn title_case(name: &str) -> String {
+    name.split_whitespace()
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Reads `x y` from the end of `words`, returning the words before them.
+fn split_position(words: &[String]) -> Option<(&[String], i32, i32)> {
+    if words.len() < 2 {
+        return None;
+    }
+    let y = words[words.len() - 1].parse().ok()?;
+    let x = words[words.len() - 2].parse().ok()?;
+    Some((&words[..words.len() - 2], x, y))
+}
+
+fn open_tile(ecs: &World, x: i32, y: i32) -> Result<(), String> {
+    let map = ecs.fetch::<Map>();
+    if x < 0 || x >= map.width || y < 0 || y >= map.height {
+        return Err(format!("{},{} is off the map", x, y));
+    }
+    let index = map.get_index_xy(x, y);
+    if !map.tiles[index].is_walkable() {
+        return Err(format!("{},{} is not walkable", x, y));
+    }
+    if map.blocked[index] {
+        return Err(format!("{},{} is occupied", x, y));
+    }
+    Ok(())
+}
+
+/// Runs one console command and returns what to print.
+fn run_command(gs: &mut State, line: &str) -> String {
+    let words = split_words(line);
+    let (command, arguments) = match words.split_first() {
+        Some((command, arguments)) => (command.to_lowercase(), arguments),
+        None => return String::new(),
+    };
+
+    // Saving the level leaves the game as it was
+    if command == "export" {
+        return export(&gs.ecs, arguments).unwrap_or_else(|message| message);
+    }
+
+    let result = match command.as_str() {
+        "spawn" => spawn(&mut gs.ecs, arguments),
+        "give" => give(&mut gs.ecs, arguments),
+        "reveal" => {
+            let mut map = gs.ecs.fetch_mut::<Map>();
+            for revealed in map.revealed_tiles.iter_mut() {
+                *revealed = true;
+            }
+            Ok("The map is revealed".to_string())
+        }
+        "godmode" => {
+            let mut wizard = gs.ecs.write_resource::<Wizard>();
+            wizard.god_mode = !wizard.god_mode;
+            Ok(format!(
+                "God mode {}",
+                if wizard.god_mode { "on" } else { "off" }
+            ))
+        }
+        "swim" => {
+            let player_entity = *gs.ecs.fetch::<Entity>();
+            let mut swimmers = gs.ecs.write_storage::<Swimmer>();
+            if swimmers.remove(player_entity).is_some() {
+                Ok("You can no longer swim".to_string())
+            } else {
+                swimmers
+                    .insert(player_entity, Swimmer {})
+                    .expect("Unable to insert swimmer");
+                Ok("You can swim".to_string())
+            }
+        }
+        "teleport" => teleport(&mut gs.ecs, arguments),
+        "descend" => {
+            gs.goto_next_level();
+            let depth = gs.ecs.fetch::<Map>().depth;
+            Ok(format!("Descended to depth {}", depth))
+        }
+        "import" => match arguments {
+            [path] => {
+                let depth = gs.ecs.fetch::<Map>().depth;
+                map_file::load_level(path, depth).map(|map| {
+                    gs.enter_level(map);
+                    format!("Loaded {}", path)
+                })
+            }
+            _ => Err("Usage: import FILE".to_string()),
+        },
+        "wfc" => match arguments {
+            [path] => wave_function_collapse(gs, path),
+            _ => Err("Usage: wfc FILE".to_string()),
+        },
+        _ => Err(
+            "Commands: spawn give reveal godmode swim teleport descend export import wfc"
+                .to_string(),
+        ),
+    };
+
+    match result {
+        Ok(message) => {
+            // The run no longer follows the rules, so it cannot be replayed
+            // or make the high-score table
+            replay::abandon(&gs.ecs, "the wizard console was used");
+            gs.ecs.write_resource::<RunRecord>().wizard = true;
+            gs.run_systems();
+            message
+        }
+        Err(message) => message,
+    }
+}
+
+fn spawn(ecs: &mut World, arguments: &[String]) -> Result<String, String> {
+    let (name, x, y) = match split_position(arguments) {
+        Some((name, x, y)) if !name.is_empty() => (title_case(&name.join(" ")), x, y),
+        _ => return Err("Usage: spawn NAME X Y".to_string()),
+    };
+    open_tile(ecs, x, y)?;
+
+    if spawner::spawn_named_monster(ecs, &name, x, y) {
+        MapIndexingSystem {}.run_now(ecs);
+        Ok(format!("Spawned {} at {},{}", name, x, y))
+    } else if spawner::spawn_named_item(ecs, &name, x, y).is_some() {
+        Ok(format!("Spawned {} at {},{}", name, x, y))
+    } else {
+        Err(format!("Nothing is called {}", name))
+    }
+}
+
+fn give(ecs: &mut World, arguments: &[String]) -> Result<String, String> {
+    if arguments.is_empty() {
+        return Err("Usage: give NAME".to_string());
+    }
+    let name = title_case(&arguments.join(" "));
+    let player_entity = *ecs.fetch::<Entity>();
+    let player_pos = *ecs.fetch::<Point>();
+
+    let item = spawner::spawn_named_item(ecs, &name, player_pos.x, player_pos.y)
+        .ok_or_else(|| format!("There is no item called {}", name))?;
+    ecs.write_storage::<Position>().remove(item);
+    ecs.write_storage::<InBackpack>()
+        .insert(
+            item,
+            InBackpack {
+                owner: player_entity,
+            },
+        )
+        .expect("Unable to insert backpack entry");
+    Ok(format!("You now have a {}", name))
+}
+
+/// `export` saves under `maps/`, `export FILE` to the given file, and a
+/// trailing `tiles` leaves out monsters, items and traps.
+fn export(ecs: &World, arguments: &[String]) -> Result<String, String> {
+    let (path, with_entities) = match arguments {
+        [] => return map_file::export_for_report(ecs).map(|path| format!("Saved {}", path)),
+        [path] => (path, true),
+        [path, tiles] if tiles == "tiles" => (path, false),
+        _ => return Err("Usage: export [FILE] [tiles]".to_string()),
+    };
+    map_file::export_level(ecs, path, with_entities)?;
+    Ok(format!("Saved {}", path))
+}
+
+/// Builds a new level from the chunks of a map file and moves there.
+fn wave_function_collapse(gs: &mut State, path: &str) -> Result<String, String> {
+    let depth = gs.ecs.fetch::<Map>().depth;
+    let template = map_file::load_template(path, depth)?;
+    let map = {
+        let mut rng = gs.ecs.write_resource::<rltk::RandomNumberGenerator>();
+        wfc_builder::build_from(depth, &template, &mut rng)
+    }
+    .ok_or_else(|| format!("{} did not give a level to stand in", path))?;
+    gs.enter_level(map);
+    Ok(format!("Built a level from {}", path))
+}
+
+fn teleport(ecs: &mut World, arguments: &[String]) -> Result<String, String> {
+    let (x, y) = match split_position(arguments) {
+        Some(([], x, y)) => (x, y),
+        _ => return Err("Usage: teleport X Y".to_string()),
+    };
+    open_tile(ecs, x, y)?;
+
+    let player_entity = *ecs.fetch::<Entity>();
+    if let Some(pos) = ecs.write_storage::<Position>().get_mut(player_entity) {
+        pos.x = x;
+        pos.y = y;
+    }
+    // Whatever else is on the old tile may still block it
+    MapIndexingSystem {}.run_now(ecs);
+    if let Some(viewshed) = ecs.write_storage::<Viewshed>().get_mut(player_entity) {
+        viewshed.dirty = true;
+    }
+    *ecs.write_resource::<Point>() = Point::new(x, y);
+    Ok(format!("Teleported to {},{}", x, y))
+}
+
+/// Console lines to draw, oldest first, followed by the prompt.
+pub fn console_lines(ecs: &World) -> (Vec<String>, String) {
+    let wizard = ecs.fetch::<Wizard>();
+    (wizard.output.clone(), format!("> {}_", wizard.input))
+}